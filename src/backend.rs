@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fmt::Debug;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{process, FlakeShowError};
+
+/// Called with every line nix prints to stderr, without the newline.
+pub type StderrLineHandler = Box<dyn FnMut(&[u8]) + Send>;
+
+/// Executes nix on behalf of the builders in this crate.
+///
+/// [`ProcessBackend`] spawns the real binary and is the default. Tests can
+/// use [`ReplayBackend`] (or their own implementation) to serve recorded
+/// output without nix being installed.
+pub trait NixBackend: Debug + Send + Sync {
+    /// Run `invocation`, returning stdout if nix exits successfully.
+    ///
+    /// `on_stderr_line`, when given, must be called with each line of
+    /// stderr, ideally while nix is still running.
+    fn run(
+        &self,
+        invocation: &NixInvocation,
+        on_stderr_line: Option<StderrLineHandler>,
+    ) -> Result<Vec<u8>, FlakeShowError>;
+}
+
+/// A single nix command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NixInvocation {
+    pub program: PathBuf,
+    pub args: Vec<OsString>,
+    /// the directory to run nix in, if not the current one.
+    pub current_dir: Option<PathBuf>,
+    pub timeout: Option<Duration>,
+}
+
+impl NixInvocation {
+    pub(crate) fn from_command(cmd: &std::process::Command, timeout: Option<Duration>) -> Self {
+        Self {
+            program: cmd.get_program().into(),
+            args: cmd.get_args().map(Into::into).collect(),
+            current_dir: cmd.get_current_dir().map(Into::into),
+            timeout,
+        }
+    }
+
+    /// The arguments as (lossily converted) strings.
+    pub fn args_lossy(&self) -> Vec<String> {
+        self.args
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    pub fn command(&self) -> std::process::Command {
+        let mut cmd = std::process::Command::new(&self.program);
+        cmd.args(&self.args);
+        if let Some(dir) = &self.current_dir {
+            cmd.current_dir(dir);
+        }
+        cmd
+    }
+}
+
+/// Runs nix as a child process.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ProcessBackend;
+
+impl NixBackend for ProcessBackend {
+    fn run(
+        &self,
+        invocation: &NixInvocation,
+        on_stderr_line: Option<StderrLineHandler>,
+    ) -> Result<Vec<u8>, FlakeShowError> {
+        process::run_captured(invocation.command(), invocation.timeout, on_stderr_line)
+    }
+}
+
+/// A recorded run of nix.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NixOutput {
+    #[serde(default)]
+    pub exit_code: i32,
+    #[serde(default)]
+    pub stdout: String,
+    #[serde(default)]
+    pub stderr: String,
+}
+
+impl NixOutput {
+    /// A successful run that printed `stdout`.
+    pub fn success(stdout: impl Into<String>) -> Self {
+        Self {
+            exit_code: 0,
+            stdout: stdout.into(),
+            stderr: String::new(),
+        }
+    }
+
+    /// A run that exited with `exit_code` after printing `stderr`.
+    pub fn failure(exit_code: i32, stderr: impl Into<String>) -> Self {
+        Self {
+            exit_code,
+            stdout: String::new(),
+            stderr: stderr.into(),
+        }
+    }
+
+    pub fn with_stderr(mut self, stderr: impl Into<String>) -> Self {
+        self.stderr = stderr.into();
+        self
+    }
+}
+
+/// Serves recorded [`NixOutput`]s instead of running nix.
+///
+/// Recordings are matched on the exact arguments nix would have been called
+/// with (the program is ignored), falling back to the one given to
+/// [`ReplayBackend::fallback`]. stderr is replayed line by line, so log
+/// event handlers see it as they would with a real nix.
+#[derive(Debug, Default, Clone)]
+pub struct ReplayBackend {
+    recordings: HashMap<Vec<String>, NixOutput>,
+    fallback: Option<NixOutput>,
+}
+
+impl ReplayBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serve `output` whenever nix is called with exactly `args`.
+    pub fn record<I, S>(mut self, args: I, output: NixOutput) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let args = args.into_iter().map(Into::into).collect();
+        self.recordings.insert(args, output);
+        self
+    }
+
+    /// Serve `output` for any invocation without a recording of its own.
+    pub fn fallback(mut self, output: NixOutput) -> Self {
+        self.fallback = Some(output);
+        self
+    }
+}
+
+impl NixBackend for ReplayBackend {
+    fn run(
+        &self,
+        invocation: &NixInvocation,
+        on_stderr_line: Option<StderrLineHandler>,
+    ) -> Result<Vec<u8>, FlakeShowError> {
+        let args = invocation.args_lossy();
+        let Some(output) = self.recordings.get(&args).or(self.fallback.as_ref()) else {
+            return Err(FlakeShowError::MissingRecording { args });
+        };
+
+        if let Some(mut on_stderr_line) = on_stderr_line {
+            for line in output.stderr.lines() {
+                on_stderr_line(line.as_bytes());
+            }
+        }
+
+        if output.exit_code == 0 {
+            Ok(output.stdout.clone().into_bytes())
+        } else {
+            Err(FlakeShowError::NonZeroExit {
+                code: Some(output.exit_code),
+                stderr: output.stderr.clone(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invocation(args: &[&str]) -> NixInvocation {
+        NixInvocation {
+            program: "nix".into(),
+            args: args.iter().map(Into::into).collect(),
+            current_dir: None,
+            timeout: None,
+        }
+    }
+
+    #[test]
+    fn replays_recordings() {
+        let backend = ReplayBackend::new()
+            .record(["eval", "--raw"], NixOutput::success("x86_64-linux"))
+            .record(
+                ["flake", "show"],
+                NixOutput::failure(1, "error: no flake.nix\n"),
+            );
+
+        assert_eq!(
+            backend.run(&invocation(&["eval", "--raw"]), None).unwrap(),
+            b"x86_64-linux"
+        );
+        assert!(matches!(
+            backend.run(&invocation(&["flake", "show"]), None),
+            Err(FlakeShowError::NonZeroExit { code: Some(1), .. })
+        ));
+        assert!(matches!(
+            backend.run(&invocation(&["build"]), None),
+            Err(FlakeShowError::MissingRecording { .. })
+        ));
+
+        let backend = backend.fallback(NixOutput::success("{}").with_stderr("one\ntwo\n"));
+        let (tx, rx) = std::sync::mpsc::channel();
+        let on_line: StderrLineHandler = Box::new(move |line| tx.send(line.to_vec()).unwrap());
+        assert_eq!(
+            backend.run(&invocation(&["build"]), Some(on_line)).unwrap(),
+            b"{}"
+        );
+        assert_eq!(rx.iter().collect::<Vec<_>>(), [&b"one"[..], b"two"]);
+    }
+}
@@ -0,0 +1,201 @@
+use std::io::{self, Write};
+
+use clap::ValueEnum;
+use nix_flake_show::FlakeInfo;
+
+use crate::rows::{rows, Filter, Row};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// the tree `nix flake show` prints.
+    Tree,
+    /// aligned columns with a header.
+    Table,
+    /// an array of objects, one per entry.
+    Json,
+    /// the same as `json`, as YAML.
+    Yaml,
+    /// tab separated columns without a header, for `cut` and `awk`.
+    Tsv,
+}
+
+const COLUMNS: [&str; 6] = [
+    "OUTPUT",
+    "SYSTEM",
+    "ATTRIBUTE",
+    "TYPE",
+    "NAME",
+    "DESCRIPTION",
+];
+
+impl Format {
+    /// Print what `filter` keeps of `info`.
+    pub fn write(self, info: &FlakeInfo, filter: &Filter, out: &mut impl Write) -> io::Result<()> {
+        let rows: Vec<_> = rows(info)
+            .into_iter()
+            .filter(|row| filter.matches(row))
+            .collect();
+        let rows = rows.as_slice();
+        match self {
+            Format::Tree => write_tree(info, filter, out),
+            Format::Table => write_table(rows, out),
+            Format::Json => {
+                serde_json::to_writer_pretty(&mut *out, rows)?;
+                writeln!(out)
+            }
+            Format::Yaml => serde_yaml::to_writer(out, rows).map_err(io::Error::other),
+            Format::Tsv => {
+                for row in rows {
+                    writeln!(out, "{}", columns(row).join("\t"))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The cells of `row`, with empty strings for missing values. Tabs and
+/// newlines in descriptions would break the line based formats.
+fn columns(row: &Row) -> [String; 6] {
+    let clean = |s: &str| s.replace(['\t', '\n'], " ");
+    [
+        row.output.clone(),
+        row.system.clone().unwrap_or_default(),
+        row.attr.clone(),
+        row.kind.clone(),
+        row.name.clone().unwrap_or_default(),
+        row.description.as_deref().map(clean).unwrap_or_default(),
+    ]
+}
+
+fn write_table(rows: &[Row], out: &mut impl Write) -> io::Result<()> {
+    let cells: Vec<_> = rows.iter().map(columns).collect();
+    let mut widths = COLUMNS.map(str::len);
+    for row in &cells {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let header = COLUMNS.map(String::from);
+    for row in std::iter::once(&header).chain(&cells) {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        writeln!(out, "{}", line.trim_end())?;
+    }
+    Ok(())
+}
+
+/// Other systems are collapsed, as nix does without `--all-systems`.
+fn write_tree(info: &FlakeInfo, filter: &Filter, out: &mut impl Write) -> io::Result<()> {
+    let mut info = info.clone();
+    filter.retain(&mut info);
+    let tree = filter
+        .systems
+        .iter()
+        .fold(info.tree(), |tree, system| tree.system(system));
+    write!(out, "{tree}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info() -> FlakeInfo {
+        FlakeInfo::from_stdout(
+            br#"{
+                "packages": {"x86_64-linux": {"hello": {"name": "hello-2.12.1", "type": "derivation", "description": "Says\thello"}}},
+                "formatter": {"x86_64-linux": {"name": "alejandra-3.0.0", "type": "derivation"}},
+                "overlays": {"default": {"type": "nixpkgs-overlay"}}
+            }"#,
+        )
+        .unwrap()
+    }
+
+    fn render_filtered(format: Format, info: &FlakeInfo, filter: &Filter) -> String {
+        let mut out = Vec::new();
+        format.write(info, filter, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn render(format: Format) -> String {
+        render_filtered(format, &info(), &Filter::default())
+    }
+
+    #[test]
+    fn tree() {
+        assert_eq!(
+            render(Format::Tree),
+            "\
+├───formatter
+│   └───x86_64-linux: package 'alejandra-3.0.0'
+├───overlays
+│   └───default: Nixpkgs overlay
+└───packages
+    └───x86_64-linux
+        └───hello: package 'hello-2.12.1' - 'Says\thello'
+"
+        );
+    }
+
+    #[test]
+    fn filtered_tree() {
+        let info = FlakeInfo::from_stdout(
+            br#"{
+                "packages": {
+                    "aarch64-darwin": {"hello": {"name": "hello-2.12.1", "type": "derivation"}},
+                    "x86_64-linux": {
+                        "hello": {"name": "hello-2.12.1", "type": "derivation"},
+                        "zlib": {"name": "zlib-1.3", "type": "derivation"}
+                    }
+                },
+                "devShells": {"x86_64-linux": {"default": {"name": "nix-shell", "type": "derivation"}}},
+                "legacyPackages": {"x86_64-linux": {"type": "omitted"}}
+            }"#,
+        )
+        .unwrap();
+        let filter = Filter {
+            outputs: vec!["packages".into()],
+            systems: vec!["x86_64-linux".into()],
+            name_regex: Some(regex::Regex::new("hello").unwrap()),
+        };
+        assert_eq!(
+            render_filtered(Format::Tree, &info, &filter),
+            "\
+└───packages
+    ├───aarch64-darwin omitted (use '--all-systems' to show)
+    └───x86_64-linux
+        └───hello: package 'hello-2.12.1'
+"
+        );
+    }
+
+    #[test]
+    fn table_and_tsv() {
+        assert_eq!(
+            render(Format::Table),
+            "OUTPUT     SYSTEM        ATTRIBUTE  TYPE             NAME             DESCRIPTION\n\
+             formatter  x86_64-linux             derivation       alejandra-3.0.0\n\
+             overlays                 default    nixpkgs-overlay\n\
+             packages   x86_64-linux  hello      derivation       hello-2.12.1     Says hello\n"
+        );
+        assert_eq!(
+            render(Format::Tsv).lines().last(),
+            Some("packages\tx86_64-linux\thello\tderivation\thello-2.12.1\tSays hello")
+        );
+    }
+
+    #[test]
+    fn json_and_yaml() {
+        let json: serde_json::Value = serde_json::from_str(&render(Format::Json)).unwrap();
+        assert_eq!(json[2]["attrPath"], "packages.x86_64-linux.hello");
+        assert_eq!(json[1]["system"], serde_json::Value::Null);
+
+        let yaml: serde_json::Value = serde_yaml::from_str(&render(Format::Yaml)).unwrap();
+        assert_eq!(yaml, json);
+    }
+}
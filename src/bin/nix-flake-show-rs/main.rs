@@ -0,0 +1,102 @@
+//! `nix flake show`, with filters and output formats suited to scripts.
+//!
+//! Exits with 0 when something was printed, [`NO_MATCHES`] when nothing
+//! matched the filters, 2 for invalid arguments, [`NIX_FAILED`] when nix
+//! failed or printed something unexpected, and [`WRITE_FAILED`] when the
+//! output couldn't be written.
+
+mod format;
+mod rows;
+
+use std::io::{self, Write};
+use std::process::ExitCode;
+
+use clap::Parser;
+use nix_flake_show::{flake_show, FlakeRef};
+use regex::Regex;
+
+use crate::format::Format;
+use crate::rows::{rows, Filter};
+
+/// Nothing in the flake matched the filters.
+const NO_MATCHES: u8 = 1;
+/// nix failed, or its output couldn't be understood.
+const NIX_FAILED: u8 = 3;
+/// Writing to stdout failed.
+const WRITE_FAILED: u8 = 4;
+
+/// Show the outputs of a flake, like `nix flake show`.
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Args {
+    /// the flake to show, the one in the current directory by default.
+    flake: Option<FlakeRef>,
+    /// only show this output, e.g. `packages` or `devShells`. Can be
+    /// given more than once.
+    #[arg(long = "output", value_name = "OUTPUT")]
+    outputs: Vec<String>,
+    /// only show this system, e.g. `x86_64-linux`. Can be given more than
+    /// once. Outputs that aren't per system, like `overlays`, are always
+    /// shown. This only filters what is printed, nix still evaluates
+    /// every system.
+    #[arg(long = "system", value_name = "SYSTEM")]
+    systems: Vec<String>,
+    /// only show entries whose attribute or derivation name matches.
+    #[arg(long, value_name = "REGEX")]
+    name_regex: Option<Regex>,
+    /// how to print the entries.
+    #[arg(long, value_enum, default_value_t = Format::Tree)]
+    format: Format,
+    /// also show `legacyPackages`, which can take a long time.
+    #[arg(long)]
+    legacy: bool,
+    /// allow access to mutable paths and repositories.
+    #[arg(long)]
+    impure: bool,
+    /// don't use cached flake sources.
+    #[arg(long)]
+    refresh: bool,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+
+    let mut show = flake_show()
+        .legacy(args.legacy)
+        .impure(args.impure)
+        .refresh(args.refresh);
+    if let Some(flake) = args.flake {
+        show = show.url(flake);
+    }
+    let info = match show.into_structured() {
+        Ok(info) => info,
+        Err(err) => {
+            eprintln!("error: {err}");
+            return ExitCode::from(NIX_FAILED);
+        }
+    };
+
+    let filter = Filter {
+        outputs: args.outputs,
+        systems: args.systems,
+        name_regex: args.name_regex,
+    };
+    if !rows(&info).iter().any(|row| filter.matches(row)) {
+        return ExitCode::from(NO_MATCHES);
+    }
+
+    let mut stdout = io::stdout().lock();
+    match args
+        .format
+        .write(&info, &filter, &mut stdout)
+        .and_then(|_| stdout.flush())
+    {
+        Ok(()) => ExitCode::SUCCESS,
+        // e.g. piped into `head`
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::from(WRITE_FAILED)
+        }
+    }
+}
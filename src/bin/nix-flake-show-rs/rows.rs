@@ -0,0 +1,383 @@
+use nix_flake_show::{App, Derivation, FlakeInfo, HydraJob, OutputNode};
+use regex::Regex;
+use serde::Serialize;
+
+/// A single entry of a flake's outputs, the unit everything is filtered
+/// and printed by.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Row {
+    /// the top level output, e.g. `packages`.
+    pub output: String,
+    /// `None` for outputs that aren't per system, like `overlays`.
+    pub system: Option<String>,
+    /// the attribute below the output and system, e.g. `hello` or
+    /// `python3Packages.requests`. Empty for `formatter`.
+    pub attr: String,
+    /// the full attribute path, e.g. `packages.x86_64-linux.hello`.
+    pub attr_path: String,
+    /// the type nix reports, e.g. `derivation`, `app` or `nixos-module`.
+    pub kind: String,
+    /// the derivation name, e.g. `hello-2.12.1`.
+    pub name: Option<String>,
+    pub description: Option<String>,
+}
+
+impl Row {
+    fn new(output: &str, system: Option<&str>, attr: &str, kind: &str) -> Self {
+        let attr_path = [Some(output), system, Some(attr).filter(|a| !a.is_empty())]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(".");
+        Row {
+            output: output.to_string(),
+            system: system.map(str::to_string),
+            attr: attr.to_string(),
+            attr_path,
+            kind: kind.to_string(),
+            name: None,
+            description: None,
+        }
+    }
+
+    fn derivation(output: &str, system: &str, deriv: &Derivation) -> Self {
+        Row {
+            name: Some(deriv.name.clone()),
+            description: deriv.description.clone(),
+            ..Row::new(output, Some(system), &deriv.invocation, &deriv.kind)
+        }
+    }
+
+    fn app(system: &str, app: &App) -> Self {
+        Row {
+            description: app.description.clone(),
+            ..Row::new("apps", Some(system), &app.invocation, "app")
+        }
+    }
+
+    fn formatter(system: &str, deriv: &Derivation) -> Self {
+        Row {
+            attr: String::new(),
+            attr_path: format!("formatter.{system}"),
+            ..Row::derivation("formatter", system, deriv)
+        }
+    }
+
+    fn hydra_job(job: &HydraJob) -> Self {
+        Row {
+            system: job.system().map(str::to_string),
+            attr: job.path.join("."),
+            attr_path: format!("hydraJobs.{}", job.path.join(".")),
+            ..Row::derivation("hydraJobs", "", &job.derivation)
+        }
+    }
+
+    fn template(name: &str, description: &str) -> Self {
+        Row {
+            description: Some(description.to_string()),
+            ..Row::new("templates", None, name, "template")
+        }
+    }
+
+    /// `leaf` at `path` below `output`, either a custom output or what a
+    /// per system output holds besides its entries.
+    fn other(output: &str, path: &[String], leaf: &OutputNode) -> Self {
+        let mut row = match path.split_first() {
+            Some((system, attr)) if PER_SYSTEM.contains(&output) => {
+                Row::new(output, Some(system), &attr.join("."), "unknown")
+            }
+            _ => Row::new(output, None, &path.join("."), "unknown"),
+        };
+        match leaf {
+            OutputNode::Leaf {
+                kind,
+                name,
+                description,
+            } => {
+                row.kind = kind.clone();
+                row.name = name.clone();
+                row.description = description.clone();
+            }
+            OutputNode::Omitted => row.kind = "omitted".to_string(),
+            OutputNode::Unknown | OutputNode::Attrs(_) => {}
+        }
+        row
+    }
+}
+
+/// Outputs laid out as `<output>.<system>.<attr>`.
+const PER_SYSTEM: &[&str] = &[
+    "apps",
+    "checks",
+    "devShells",
+    "formatter",
+    "legacyPackages",
+    "packages",
+];
+
+/// Every entry of `info`, sorted by output, system and attribute.
+pub fn rows(info: &FlakeInfo) -> Vec<Row> {
+    let mut rows = Vec::new();
+
+    for (system, apps) in &info.apps {
+        rows.extend(apps.iter().map(|app| Row::app(system, app)));
+    }
+
+    for (output, by_system) in [
+        ("checks", &info.checks),
+        ("devShells", &info.dev_shells),
+        ("packages", &info.packages),
+        ("legacyPackages", &info.legacy_packages),
+    ] {
+        for (system, derivs) in by_system {
+            rows.extend(derivs.iter().map(|d| Row::derivation(output, system, d)));
+        }
+    }
+
+    for (system, deriv) in &info.formatter {
+        rows.push(Row::formatter(system, deriv));
+    }
+
+    rows.extend(info.hydra_jobs.iter().map(Row::hydra_job));
+
+    rows.extend(
+        info.overlays
+            .iter()
+            .map(|overlay| Row::new("overlays", None, &overlay.name, &overlay.kind)),
+    );
+    rows.extend(
+        info.nixos_modules
+            .iter()
+            .map(|module| Row::new("nixosModules", None, &module.name, &module.kind)),
+    );
+    for (output, configs) in [
+        ("nixosConfigurations", &info.nixos_configurations),
+        ("darwinConfigurations", &info.darwin_configurations),
+        ("homeConfigurations", &info.home_configurations),
+    ] {
+        rows.extend(
+            configs
+                .iter()
+                .map(|config| Row::new(output, None, &config.name, &config.kind)),
+        );
+    }
+
+    if let Some(lib) = &info.lib {
+        for (path, leaf) in lib.leaves() {
+            rows.push(Row::other("lib", &path, leaf));
+        }
+    }
+
+    rows.extend(
+        info.templates
+            .iter()
+            .map(|(name, description)| Row::template(name, description)),
+    );
+
+    for (output, node) in &info.other_outputs {
+        for (path, leaf) in node.leaves() {
+            rows.push(Row::other(output, &path, leaf));
+        }
+    }
+
+    rows.sort_by(|a, b| (&a.output, &a.system, &a.attr).cmp(&(&b.output, &b.system, &b.attr)));
+    rows
+}
+
+/// What to keep, every filter left empty keeps everything.
+#[derive(Debug, Default)]
+pub struct Filter {
+    pub outputs: Vec<String>,
+    pub systems: Vec<String>,
+    pub name_regex: Option<Regex>,
+}
+
+impl Filter {
+    /// Outputs that aren't per system are kept regardless of `systems`, as
+    /// they're available on all of them.
+    pub fn matches(&self, row: &Row) -> bool {
+        let output = self.outputs.is_empty() || self.outputs.contains(&row.output);
+        let system = match &row.system {
+            Some(system) => self.systems.is_empty() || self.systems.contains(system),
+            None => true,
+        };
+        let name = match &self.name_regex {
+            Some(regex) => {
+                regex.is_match(&row.attr) || row.name.as_deref().is_some_and(|n| regex.is_match(n))
+            }
+            None => true,
+        };
+        output && system && name
+    }
+
+    /// Drop everything from `info` that [`Filter::matches`] wouldn't keep,
+    /// for printing it as a tree. Per system outputs keep their systems, so
+    /// the tree can show the ones not asked for as omitted, like nix does.
+    pub fn retain(&self, info: &mut FlakeInfo) {
+        let any_system = Filter {
+            outputs: self.outputs.clone(),
+            systems: Vec::new(),
+            name_regex: self.name_regex.clone(),
+        };
+        let output_kept = |output: &str| {
+            self.outputs.is_empty() || self.outputs.iter().any(|kept| kept == output)
+        };
+        // systems nix omitted have no entries to match, they stay as long
+        // as their output does
+        let keep_system = |output: &str, was_empty: bool, is_empty: bool| {
+            !is_empty || (was_empty && output_kept(output))
+        };
+
+        info.apps.retain(|system, apps| {
+            let was_empty = apps.is_empty();
+            apps.retain(|app| any_system.matches(&Row::app(system, app)));
+            keep_system("apps", was_empty, apps.is_empty())
+        });
+        for (output, by_system) in [
+            ("checks", &mut info.checks),
+            ("devShells", &mut info.dev_shells),
+            ("packages", &mut info.packages),
+            ("legacyPackages", &mut info.legacy_packages),
+        ] {
+            by_system.retain(|system, derivs| {
+                let was_empty = derivs.is_empty();
+                derivs.retain(|d| any_system.matches(&Row::derivation(output, system, d)));
+                keep_system(output, was_empty, derivs.is_empty())
+            });
+        }
+        info.formatter
+            .retain(|system, deriv| any_system.matches(&Row::formatter(system, deriv)));
+        info.omitted
+            .retain(|path| path.first().is_some_and(|output| output_kept(output)));
+
+        info.hydra_jobs
+            .retain(|job| self.matches(&Row::hydra_job(job)));
+        info.overlays.retain(|overlay| {
+            self.matches(&Row::new("overlays", None, &overlay.name, &overlay.kind))
+        });
+        info.nixos_modules.retain(|module| {
+            self.matches(&Row::new("nixosModules", None, &module.name, &module.kind))
+        });
+        for (output, configs) in [
+            ("nixosConfigurations", &mut info.nixos_configurations),
+            ("darwinConfigurations", &mut info.darwin_configurations),
+            ("homeConfigurations", &mut info.home_configurations),
+        ] {
+            configs
+                .retain(|config| self.matches(&Row::new(output, None, &config.name, &config.kind)));
+        }
+        info.lib = info.lib.take().and_then(|mut lib| {
+            self.retain_other("lib", &mut Vec::new(), &mut lib)
+                .then_some(lib)
+        });
+        info.templates
+            .retain(|name, description| self.matches(&Row::template(name, description)));
+
+        info.other_outputs.retain(|output, node| {
+            let filter = if PER_SYSTEM.contains(&output.as_str()) {
+                &any_system
+            } else {
+                self
+            };
+            filter.retain_other(output, &mut Vec::new(), node)
+        });
+    }
+
+    /// Prune `node` at `path` below `output`, returning whether anything
+    /// is left of it.
+    fn retain_other(&self, output: &str, path: &mut Vec<String>, node: &mut OutputNode) -> bool {
+        match node {
+            OutputNode::Attrs(children) => {
+                children.retain(|attr, child| {
+                    path.push(attr.clone());
+                    let keep = self.retain_other(output, path, child);
+                    path.pop();
+                    keep
+                });
+                !children.is_empty()
+            }
+            leaf => self.matches(&Row::other(output, path, leaf)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHOW: &str = r#"{
+        "packages": {
+            "x86_64-linux": {
+                "hello": {"name": "hello-2.12.1", "type": "derivation", "description": "Says hello"},
+                "default": {"name": "hello-2.12.1", "type": "derivation"}
+            },
+            "aarch64-darwin": {"hello": {"name": "hello-2.12.1", "type": "derivation"}}
+        },
+        "formatter": {"x86_64-linux": {"name": "alejandra-3.0.0", "type": "derivation"}},
+        "overlays": {"default": {"type": "nixpkgs-overlay"}},
+        "templates": {"rust": {"description": "A Rust crate"}}
+    }"#;
+
+    fn info() -> FlakeInfo {
+        FlakeInfo::from_stdout(SHOW.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn flattens_and_sorts() {
+        let rows = rows(&info());
+        assert_eq!(
+            rows.iter()
+                .map(|row| row.attr_path.as_str())
+                .collect::<Vec<_>>(),
+            [
+                "formatter.x86_64-linux",
+                "overlays.default",
+                "packages.aarch64-darwin.hello",
+                "packages.x86_64-linux.default",
+                "packages.x86_64-linux.hello",
+                "templates.rust",
+            ]
+        );
+        assert_eq!(rows[4].name.as_deref(), Some("hello-2.12.1"));
+        assert_eq!(rows[4].description.as_deref(), Some("Says hello"));
+    }
+
+    #[test]
+    fn filters() {
+        let rows = rows(&info());
+        let kept = |filter: Filter| {
+            rows.iter()
+                .filter(|row| filter.matches(row))
+                .map(|row| row.attr_path.as_str())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            kept(Filter {
+                outputs: vec!["packages".into()],
+                systems: vec!["x86_64-linux".into()],
+                ..Filter::default()
+            }),
+            [
+                "packages.x86_64-linux.default",
+                "packages.x86_64-linux.hello"
+            ]
+        );
+        assert_eq!(
+            kept(Filter {
+                systems: vec!["aarch64-darwin".into()],
+                name_regex: Some(Regex::new("^(hello|rust)$").unwrap()),
+                ..Filter::default()
+            }),
+            ["packages.aarch64-darwin.hello", "templates.rust"]
+        );
+        assert_eq!(
+            kept(Filter {
+                name_regex: Some(Regex::new("alejandra").unwrap()),
+                ..Filter::default()
+            }),
+            ["formatter.x86_64-linux"]
+        );
+    }
+}
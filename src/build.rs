@@ -0,0 +1,326 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::common::{common_setters, CommonArgs};
+use crate::{Derivation, FlakeRef, FlakeShowError};
+
+/// Something nix can build: `nixpkgs#hello`, `.#packages.x86_64-linux.default`,
+/// a store path, ...
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Installable(String);
+
+impl Installable {
+    /// `flake#attr.path`, quoting attributes that nix would otherwise split
+    /// up, such as `"python3.11"`.
+    pub fn flake_attr<S: AsRef<str>>(flake: impl Into<FlakeRef>, attr_path: &[S]) -> Self {
+        let attr_path: Vec<String> = attr_path
+            .iter()
+            .map(|attr| quote_attr(attr.as_ref()))
+            .collect();
+        Installable(format!("{}#{}", flake.into(), attr_path.join(".")))
+    }
+
+    /// A derivation of `flake`, as reported by [`crate::flake_show`].
+    pub fn derivation(flake: impl Into<FlakeRef>, derivation: &Derivation) -> Self {
+        Self::flake_attr(flake, &derivation.attr_path)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// `attr` as it appears in an attribute path, quoted unless nix would read
+/// it as a single plain attribute.
+pub(crate) fn quote_attr(attr: &str) -> String {
+    let plain = !attr.is_empty()
+        && attr
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_'-".contains(c));
+    if plain {
+        attr.to_string()
+    } else {
+        format!("\"{attr}\"")
+    }
+}
+
+/// Split an attribute path as nix prints it, e.g.
+/// `legacyPackages.x86_64-linux."python3.11"`, undoing [`quote_attr`].
+pub(crate) fn split_attr_path(path: &str) -> Vec<String> {
+    let mut attrs = Vec::new();
+    let mut attr = String::new();
+    let mut quoted = false;
+    for c in path.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            '.' if !quoted => attrs.push(std::mem::take(&mut attr)),
+            c => attr.push(c),
+        }
+    }
+    attrs.push(attr);
+    attrs
+}
+
+impl From<&str> for Installable {
+    fn from(value: &str) -> Self {
+        Installable(value.to_string())
+    }
+}
+
+impl From<String> for Installable {
+    fn from(value: String) -> Self {
+        Installable(value)
+    }
+}
+
+impl From<&Path> for Installable {
+    fn from(value: &Path) -> Self {
+        Installable(value.display().to_string())
+    }
+}
+
+impl fmt::Display for Installable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// One entry of `nix build --json`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BuildResult {
+    pub drv_path: PathBuf,
+    /// from output name (`out`, `dev`, `man`, ...) to its store path.
+    pub outputs: BTreeMap<String, PathBuf>,
+    /// unix timestamps of when nix started and stopped building, both `0`
+    /// if nothing had to be built. Only reported by newer versions of nix.
+    #[serde(default)]
+    pub start_time: Option<u64>,
+    #[serde(default)]
+    pub stop_time: Option<u64>,
+}
+
+impl BuildResult {
+    pub fn from_stdout(v: &[u8]) -> Result<Vec<Self>, FlakeShowError> {
+        serde_json::from_slice(v).map_err(|err| FlakeShowError::json(err, v))
+    }
+
+    /// The store path of the `out` output, or of the only output there is.
+    pub fn out_path(&self) -> Option<&Path> {
+        match self.outputs.get("out") {
+            Some(path) => Some(path),
+            None if self.outputs.len() == 1 => self.outputs.values().next().map(PathBuf::as_path),
+            None => None,
+        }
+    }
+
+    /// How long nix spent building this, `None` if it didn't build it or
+    /// didn't say.
+    pub fn duration(&self) -> Option<Duration> {
+        match (self.start_time?, self.stop_time?) {
+            (0, _) => None,
+            (start, stop) => Some(Duration::from_secs(stop.saturating_sub(start))),
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct NixBuildBuilder {
+    installables: Vec<Installable>,
+    out_link: Option<PathBuf>,
+    no_link: bool,
+    print_out_paths: bool,
+    json: bool,
+    keep_going: bool,
+    max_jobs: Option<usize>,
+    rebuild: bool,
+    common: CommonArgs,
+}
+
+impl NixBuildBuilder {
+    common_setters!();
+
+    /// Add something to build. Without any, nix builds the flake in the
+    /// current directory.
+    pub fn installable(mut self, installable: impl Into<Installable>) -> Self {
+        self.installables.push(installable.into());
+        self
+    }
+
+    /// Build a derivation of `flake`, as reported by [`crate::flake_show`].
+    pub fn derivation(self, flake: impl Into<FlakeRef>, derivation: &Derivation) -> Self {
+        self.installable(Installable::derivation(flake, derivation))
+    }
+
+    /// Where to put the `result` symlink.
+    pub fn out_link(mut self, out_link: impl Into<PathBuf>) -> Self {
+        self.out_link = Some(out_link.into());
+        self
+    }
+
+    pub fn no_link(mut self, no_link: bool) -> Self {
+        self.no_link = no_link;
+        self
+    }
+
+    pub fn print_out_paths(mut self, print_out_paths: bool) -> Self {
+        self.print_out_paths = print_out_paths;
+        self
+    }
+
+    pub fn json(mut self, json: bool) -> Self {
+        self.json = json;
+        self
+    }
+
+    pub fn keep_going(mut self, keep_going: bool) -> Self {
+        self.keep_going = keep_going;
+        self
+    }
+
+    pub fn max_jobs(mut self, max_jobs: usize) -> Self {
+        self.max_jobs = Some(max_jobs);
+        self
+    }
+
+    /// Build again even if the outputs are already valid, to check that
+    /// the build is deterministic.
+    pub fn rebuild(mut self, rebuild: bool) -> Self {
+        self.rebuild = rebuild;
+        self
+    }
+
+    /// Build everything, returning one [`BuildResult`] per installable.
+    /// Like `nix build` itself, this leaves `result` symlinks behind unless
+    /// told otherwise with [`NixBuildBuilder::no_link`].
+    pub fn into_results(mut self) -> Result<Vec<BuildResult>, FlakeShowError> {
+        let runner = self.common.take_runner();
+        let stdout = runner.run(&self.json(true).build())?;
+        BuildResult::from_stdout(&stdout)
+    }
+
+    pub fn build(self) -> std::process::Command {
+        let mut cmd = self.common.command(&["build"]);
+
+        for installable in &self.installables {
+            cmd.arg(installable.as_str());
+        }
+
+        if let Some(out_link) = &self.out_link {
+            cmd.arg("--out-link").arg(out_link);
+        }
+
+        if self.no_link {
+            cmd.arg("--no-link");
+        }
+
+        if self.print_out_paths {
+            cmd.arg("--print-out-paths");
+        }
+
+        if self.json {
+            cmd.arg("--json");
+        }
+
+        if self.keep_going {
+            cmd.arg("--keep-going");
+        }
+
+        if let Some(max_jobs) = self.max_jobs {
+            cmd.arg("--max-jobs").arg(max_jobs.to_string());
+        }
+
+        if self.rebuild {
+            cmd.arg("--rebuild");
+        }
+
+        self.common.push_flags(&mut cmd);
+        cmd
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{nix_build, FlakeInfo, NixOutput, ReplayBackend};
+
+    #[test]
+    fn installables() {
+        assert_eq!(
+            Installable::flake_attr(
+                FlakeRef::github("NixOS", "nixpkgs"),
+                &["legacyPackages", "x86_64-linux", "python3.11", "requests"]
+            )
+            .as_str(),
+            "github:NixOS/nixpkgs#legacyPackages.x86_64-linux.\"python3.11\".requests"
+        );
+        assert_eq!(
+            split_attr_path("legacyPackages.x86_64-linux.\"python3.11\".requests"),
+            ["legacyPackages", "x86_64-linux", "python3.11", "requests"]
+        );
+    }
+
+    #[test]
+    fn build_a_shown_derivation() {
+        let info = FlakeInfo::from_stdout(
+            br#"{"packages": {"x86_64-linux": {"hello": {"name": "hello-2.12.1", "type": "derivation"}}}}"#,
+        )
+        .unwrap();
+        let hello = &info.for_system("x86_64-linux").packages[0];
+
+        let backend = ReplayBackend::new().record(
+            [
+                "build",
+                "github:NixOS/nixpkgs#packages.x86_64-linux.hello",
+                "--no-link",
+                "--json",
+                "--max-jobs",
+                "4",
+            ],
+            NixOutput::success(
+                r#"[{"drvPath":"/nix/store/q3k8h8mm8yq4qdlzhxq3vj4w8k2vf3r7-hello-2.12.1.drv","outputs":{"out":"/nix/store/1q8w6gl1ll0mwfkqc3c2yx005s6wwfrl-hello-2.12.1"},"startTime":1710000000,"stopTime":1710000042}]"#,
+            ),
+        );
+
+        let results = nix_build()
+            .derivation(FlakeRef::github("NixOS", "nixpkgs"), hello)
+            .no_link(true)
+            .max_jobs(4)
+            .backend(backend)
+            .into_results()
+            .unwrap();
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(
+            results[0].out_path(),
+            Some(Path::new(
+                "/nix/store/1q8w6gl1ll0mwfkqc3c2yx005s6wwfrl-hello-2.12.1"
+            ))
+        );
+        assert_eq!(results[0].duration(), Some(Duration::from_secs(42)));
+    }
+
+    #[test]
+    fn parse_results() {
+        let results = BuildResult::from_stdout(
+            br#"[
+                {"drvPath": "/nix/store/a-multi.drv", "outputs": {"dev": "/nix/store/b-multi-dev", "out": "/nix/store/c-multi"}, "startTime": 0, "stopTime": 0},
+                {"drvPath": "/nix/store/d-old.drv", "outputs": {"bin": "/nix/store/e-old-bin"}}
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(results[0].outputs.len(), 2);
+        assert_eq!(results[0].out_path(), Some(Path::new("/nix/store/c-multi")));
+        assert_eq!(results[0].duration(), None);
+        assert_eq!(results[1].start_time, None);
+        assert_eq!(
+            results[1].out_path(),
+            Some(Path::new("/nix/store/e-old-bin"))
+        );
+    }
+}
@@ -0,0 +1,522 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::{Derivation, FlakeInfo};
+
+/// A table of one kind of output, with a column per system for outputs
+/// that are per system.
+struct Section {
+    title: &'static str,
+    /// the heading of the first column, e.g. `Package`.
+    column: &'static str,
+    systems: Vec<String>,
+    entries: Vec<Entry>,
+}
+
+#[derive(Default)]
+struct Entry {
+    attr: String,
+    description: Option<String>,
+    /// the command that uses this entry, e.g. `nix build .#hello`.
+    command: Option<String>,
+    systems: BTreeSet<String>,
+    version: Option<String>,
+    license: Option<String>,
+    homepage: Option<String>,
+}
+
+impl Section {
+    fn has_commands(&self) -> bool {
+        self.entries.iter().any(|entry| entry.command.is_some())
+    }
+
+    /// Only show version and license columns if something was enriched.
+    fn has_meta(&self) -> bool {
+        self.entries
+            .iter()
+            .any(|entry| entry.version.is_some() || entry.license.is_some())
+    }
+}
+
+/// Renders a [`FlakeInfo`] as a catalog for documentation: a section per
+/// output with descriptions, the systems each entry is available on, and
+/// commands to copy. Version, license and homepage are included for
+/// derivations enriched with [`crate::derivation_meta`].
+#[derive(Debug, Clone)]
+pub struct CatalogRenderer<'a> {
+    info: &'a FlakeInfo,
+    flake: String,
+    title: Option<String>,
+}
+
+impl FlakeInfo {
+    pub fn catalog(&self) -> CatalogRenderer<'_> {
+        CatalogRenderer::new(self)
+    }
+}
+
+impl<'a> CatalogRenderer<'a> {
+    pub fn new(info: &'a FlakeInfo) -> Self {
+        CatalogRenderer {
+            info,
+            flake: ".".to_string(),
+            title: None,
+        }
+    }
+
+    /// The flake reference used in commands, `.` by default.
+    pub fn flake(mut self, flake: impl std::fmt::Display) -> Self {
+        self.flake = flake.to_string();
+        self
+    }
+
+    /// The top heading, the flake reference by default.
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    fn title_or_flake(&self) -> &str {
+        self.title.as_deref().unwrap_or(&self.flake)
+    }
+
+    /// `<flake>#<attr>`, quoted for the shell if needed.
+    fn installable(&self, attr: &str) -> String {
+        let installable = format!("{}#{attr}", self.flake);
+        let safe = installable
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "#.:/_-+=@~".contains(c));
+        if safe {
+            installable
+        } else {
+            format!("'{}'", installable.replace('\'', r"'\''"))
+        }
+    }
+
+    fn command(&self, subcommand: &str, attr: &str) -> String {
+        format!("nix {subcommand} {}", self.installable(attr))
+    }
+
+    fn per_system(
+        &self,
+        title: &'static str,
+        column: &'static str,
+        by_system: impl IntoIterator<Item = (&'a String, &'a Vec<Derivation>)>,
+        subcommand: Option<&str>,
+    ) -> Section {
+        let mut entries: BTreeMap<&str, Entry> = BTreeMap::new();
+        for (system, derivs) in by_system {
+            for deriv in derivs {
+                let entry = entries.entry(&deriv.invocation).or_insert_with(|| Entry {
+                    attr: deriv.invocation.clone(),
+                    command: subcommand.map(|sub| self.command(sub, &deriv.invocation)),
+                    ..Entry::default()
+                });
+                entry.systems.insert(system.clone());
+                if entry.description.is_none() {
+                    entry.description = deriv.description.clone();
+                }
+                if let (None, Some(meta)) = (&entry.version, &deriv.meta) {
+                    entry.version = meta.version.clone();
+                    let licenses: Vec<_> = meta
+                        .license
+                        .iter()
+                        .filter_map(|l| l.spdx_id.as_ref().or(l.full_name.as_ref()))
+                        .map(String::as_str)
+                        .collect();
+                    entry.license = (!licenses.is_empty()).then(|| licenses.join(", "));
+                    entry.homepage = meta.homepage.clone();
+                }
+            }
+        }
+        let systems: BTreeSet<_> = entries
+            .values()
+            .flat_map(|entry| entry.systems.iter().cloned())
+            .collect();
+        Section {
+            title,
+            column,
+            systems: systems.into_iter().collect(),
+            entries: entries.into_values().collect(),
+        }
+    }
+
+    fn sections(&self) -> Vec<Section> {
+        let info = self.info;
+        let mut sections = vec![
+            self.per_system("Packages", "Package", &info.packages, Some("build")),
+            self.per_system("Dev shells", "Shell", &info.dev_shells, Some("develop")),
+        ];
+
+        let mut apps: BTreeMap<&str, Entry> = BTreeMap::new();
+        for (system, system_apps) in &info.apps {
+            for app in system_apps {
+                let entry = apps.entry(&app.invocation).or_insert_with(|| Entry {
+                    attr: app.invocation.clone(),
+                    description: app.description.clone(),
+                    command: Some(self.command("run", &app.invocation)),
+                    ..Entry::default()
+                });
+                entry.systems.insert(system.clone());
+            }
+        }
+        let app_systems: BTreeSet<_> = apps
+            .values()
+            .flat_map(|entry| entry.systems.iter().cloned())
+            .collect();
+        sections.push(Section {
+            title: "Apps",
+            column: "App",
+            systems: app_systems.into_iter().collect(),
+            entries: apps.into_values().collect(),
+        });
+
+        sections.push(Section {
+            title: "Templates",
+            column: "Template",
+            systems: Vec::new(),
+            entries: info
+                .templates
+                .iter()
+                .map(|(name, description)| Entry {
+                    attr: name.clone(),
+                    description: Some(description.clone()),
+                    command: Some(self.command("flake init -t", name)),
+                    ..Entry::default()
+                })
+                .collect(),
+        });
+
+        let names = |title, column, names: BTreeSet<&String>| Section {
+            title,
+            column,
+            systems: Vec::new(),
+            entries: names
+                .into_iter()
+                .map(|name| Entry {
+                    attr: name.clone(),
+                    ..Entry::default()
+                })
+                .collect(),
+        };
+        sections.push(names(
+            "Overlays",
+            "Overlay",
+            info.overlays.iter().map(|o| &o.name).collect(),
+        ));
+        sections.push(names(
+            "NixOS modules",
+            "Module",
+            info.nixos_modules.iter().map(|m| &m.name).collect(),
+        ));
+        sections.push(names(
+            "NixOS configurations",
+            "Configuration",
+            info.nixos_configurations.iter().map(|c| &c.name).collect(),
+        ));
+
+        sections.retain(|section| !section.entries.is_empty());
+        sections
+    }
+
+    /// The catalog as GitHub flavoured markdown.
+    pub fn to_markdown(&self) -> String {
+        let mut out = format!("# {}\n", markdown_text(self.title_or_flake()));
+        for section in self.sections() {
+            let mut header = vec![section.column.to_string(), "Description".to_string()];
+            if section.has_meta() {
+                header.extend(["Version".to_string(), "License".to_string()]);
+            }
+            if section.has_commands() {
+                header.push("Command".to_string());
+            }
+            let columns = header.len();
+            header.extend(section.systems.iter().map(|system| format!("`{system}`")));
+
+            let _ = write!(
+                out,
+                "\n## {}\n\n| {} |\n|",
+                section.title,
+                header.join(" | ")
+            );
+            for i in 0..header.len() {
+                out.push_str(if i < columns { " --- |" } else { " :-: |" });
+            }
+            out.push('\n');
+
+            for entry in &section.entries {
+                let attr = match entry.homepage.as_deref() {
+                    Some(homepage) if is_web_url(homepage) => {
+                        format!("[`{}`]({})", entry.attr, markdown_link_target(homepage))
+                    }
+                    Some(homepage) => format!("`{}` {}", entry.attr, markdown_cell(Some(homepage))),
+                    None => format!("`{}`", entry.attr),
+                };
+                let mut row = vec![attr, markdown_cell(entry.description.as_deref())];
+                if section.has_meta() {
+                    row.push(markdown_cell(entry.version.as_deref()));
+                    row.push(markdown_cell(entry.license.as_deref()));
+                }
+                if section.has_commands() {
+                    row.push(match &entry.command {
+                        Some(command) => format!("`{command}`"),
+                        None => String::new(),
+                    });
+                }
+                row.extend(section.systems.iter().map(|system| {
+                    let available = entry.systems.contains(system);
+                    (if available { "✓" } else { "" }).to_string()
+                }));
+                let _ = writeln!(out, "| {} |", row.join(" | "));
+            }
+        }
+        out
+    }
+
+    /// The catalog as a single HTML page, styles included.
+    pub fn to_html(&self) -> String {
+        let title = html_escape(self.title_or_flake());
+        let mut out = format!(
+            "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>{title}</title>\n<style>\n{STYLE}</style>\n</head>\n<body>\n<h1>{title}</h1>\n"
+        );
+        for section in self.sections() {
+            let _ = write!(
+                out,
+                "<section>\n<h2>{}</h2>\n<table>\n<thead><tr><th>{}</th><th>Description</th>",
+                section.title, section.column
+            );
+            if section.has_meta() {
+                out.push_str("<th>Version</th><th>License</th>");
+            }
+            if section.has_commands() {
+                out.push_str("<th>Command</th>");
+            }
+            for system in &section.systems {
+                let _ = write!(out, "<th class=\"system\">{}</th>", html_escape(system));
+            }
+            out.push_str("</tr></thead>\n<tbody>\n");
+
+            for entry in &section.entries {
+                let attr = html_escape(&entry.attr);
+                let attr = match entry.homepage.as_deref() {
+                    Some(homepage) if is_web_url(homepage) => {
+                        format!(
+                            "<a href=\"{}\"><code>{attr}</code></a>",
+                            html_escape(homepage)
+                        )
+                    }
+                    Some(homepage) => format!("<code>{attr}</code> {}", html_escape(homepage)),
+                    None => format!("<code>{attr}</code>"),
+                };
+                let cell = |text: Option<&str>| html_escape(text.unwrap_or(""));
+                let _ = write!(
+                    out,
+                    "<tr><td>{attr}</td><td>{}</td>",
+                    cell(entry.description.as_deref())
+                );
+                if section.has_meta() {
+                    let _ = write!(
+                        out,
+                        "<td>{}</td><td>{}</td>",
+                        cell(entry.version.as_deref()),
+                        cell(entry.license.as_deref())
+                    );
+                }
+                if section.has_commands() {
+                    match &entry.command {
+                        Some(command) => {
+                            let _ = write!(
+                                out,
+                                "<td><code class=\"command\">{}</code></td>",
+                                html_escape(command)
+                            );
+                        }
+                        None => out.push_str("<td></td>"),
+                    }
+                }
+                for system in &section.systems {
+                    let available = entry.systems.contains(system);
+                    out.push_str(if available {
+                        "<td class=\"system\">✓</td>"
+                    } else {
+                        "<td class=\"system\"></td>"
+                    });
+                }
+                out.push_str("</tr>\n");
+            }
+            out.push_str("</tbody>\n</table>\n</section>\n");
+        }
+        out.push_str("</body>\n</html>\n");
+        out
+    }
+}
+
+const STYLE: &str = "\
+body { font-family: system-ui, sans-serif; margin: 2rem auto; max-width: 72rem; padding: 0 1rem; }
+table { border-collapse: collapse; width: 100%; margin-bottom: 2rem; }
+th, td { border-bottom: 1px solid #ddd; padding: 0.4rem 0.6rem; text-align: left; vertical-align: top; }
+th.system, td.system { text-align: center; }
+code { font-family: ui-monospace, monospace; }
+code.command { user-select: all; background: #f4f4f4; padding: 0.1rem 0.3rem; }
+";
+
+/// GitHub passes `<...>` through as HTML, so e.g. `<Rust>` would vanish.
+fn markdown_text(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Pipes and line breaks would also end the table cell.
+fn markdown_cell(text: Option<&str>) -> String {
+    markdown_text(text.unwrap_or(""))
+        .replace('|', r"\|")
+        .replace(['\r', '\n'], " ")
+}
+
+/// A parenthesis or space would end the link early.
+fn markdown_link_target(url: &str) -> String {
+    let mut out = String::with_capacity(url.len());
+    for c in url.chars() {
+        match c {
+            '(' | ')' | '<' | '>' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            ' ' => out.push_str("%20"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Homepages are only linked when they are web pages, so a flake can't
+/// slip `javascript:` into the published catalog.
+fn is_web_url(url: &str) -> bool {
+    let lower = url.to_ascii_lowercase();
+    lower.starts_with("https://") || lower.starts_with("http://")
+}
+
+fn html_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DerivationMeta;
+
+    const SHOW: &str = r#"{
+        "packages": {
+            "x86_64-linux": {
+                "hello": {"name": "hello-2.12.1", "type": "derivation", "description": "Says | hello"},
+                "tool": {"name": "tool-1.0", "type": "derivation"}
+            },
+            "aarch64-darwin": {"hello": {"name": "hello-2.12.1", "type": "derivation"}}
+        },
+        "devShells": {"x86_64-linux": {"default": {"name": "nix-shell", "type": "derivation"}}},
+        "templates": {"rust": {"description": "A <Rust> crate"}}
+    }"#;
+
+    fn info() -> FlakeInfo {
+        let mut info = FlakeInfo::from_stdout(SHOW.as_bytes()).unwrap();
+        for deriv in info.packages.get_mut("x86_64-linux").unwrap() {
+            if deriv.invocation == "hello" {
+                deriv.meta = Some(DerivationMeta {
+                    version: Some("2.12.1".into()),
+                    homepage: Some("https://www.gnu.org/software/hello/".into()),
+                    ..DerivationMeta::default()
+                });
+            }
+        }
+        info
+    }
+
+    #[test]
+    fn markdown() {
+        let info = info();
+        assert_eq!(
+            info.catalog().flake("github:me/app").to_markdown(),
+            "\
+# github:me/app
+
+## Packages
+
+| Package | Description | Version | License | Command | `aarch64-darwin` | `x86_64-linux` |
+| --- | --- | --- | --- | --- | :-: | :-: |
+| [`hello`](https://www.gnu.org/software/hello/) | Says \\| hello | 2.12.1 |  | `nix build github:me/app#hello` | ✓ | ✓ |
+| `tool` |  |  |  | `nix build github:me/app#tool` |  | ✓ |
+
+## Dev shells
+
+| Shell | Description | Command | `x86_64-linux` |
+| --- | --- | --- | :-: |
+| `default` |  | `nix develop github:me/app#default` | ✓ |
+
+## Templates
+
+| Template | Description | Command |
+| --- | --- | --- |
+| `rust` | A &lt;Rust&gt; crate | `nix flake init -t github:me/app#rust` |
+"
+        );
+    }
+
+    #[test]
+    fn html() {
+        let info = info();
+        let html = info.catalog().title("Our flake").to_html();
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<h1>Our flake</h1>"));
+        assert!(html.contains("<td>A &lt;Rust&gt; crate</td>"));
+        assert!(html.contains("<code class=\"command\">nix flake init -t .#rust</code>"));
+        assert!(html.contains("<th class=\"system\">aarch64-darwin</th>"));
+        assert!(!html.contains("<section>\n<h2>Apps"));
+    }
+
+    #[test]
+    fn homepages() {
+        let mut info = info();
+        let packages = info.packages.get_mut("x86_64-linux").unwrap();
+        packages[0].meta.as_mut().unwrap().homepage =
+            Some("https://en.wikipedia.org/wiki/Hello_(program)".into());
+        packages[1].meta = Some(DerivationMeta {
+            homepage: Some("javascript:alert(1)".into()),
+            ..DerivationMeta::default()
+        });
+
+        let markdown = info.catalog().to_markdown();
+        assert!(
+            markdown.contains("| [`hello`](https://en.wikipedia.org/wiki/Hello_\\(program\\)) |")
+        );
+        assert!(markdown.contains("| `tool` javascript:alert(1) |"));
+
+        let html = info.catalog().to_html();
+        assert!(html.contains(
+            "<a href=\"https://en.wikipedia.org/wiki/Hello_(program)\"><code>hello</code></a>"
+        ));
+        assert!(html.contains("<td><code>tool</code> javascript:alert(1)</td>"));
+        assert!(!html.contains("href=\"javascript:"));
+    }
+
+    #[test]
+    fn odd_attributes_are_quoted() {
+        let info = info();
+        assert_eq!(
+            info.catalog().command("build", "it's"),
+            r"nix build '.#it'\''s'"
+        );
+    }
+}
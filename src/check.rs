@@ -0,0 +1,401 @@
+use std::collections::BTreeMap;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+
+use crate::build::{quote_attr, split_attr_path};
+use crate::common::{common_setters, CommonArgs};
+use crate::log::strip_ansi;
+use crate::{FlakeRef, FlakeShowError, Installable, NixLogEvent, Verbosity};
+
+/// Maps every check to its derivation, so build failures (which only name
+/// the derivation) can be attributed to a check.
+const DRV_PATHS: &str = "checks: builtins.mapAttrs (system: builtins.mapAttrs (name: check: \
+    let drv = builtins.tryEval (check.drvPath or null); in if drv.success then drv.value else null)) checks";
+
+/// From system to check name to its derivation, `None` for checks that
+/// don't evaluate.
+type DrvPaths = BTreeMap<String, BTreeMap<String, Option<String>>>;
+
+/// The outcome of `nix flake check`, see [`NixFlakeCheckBuilder::into_report`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CheckReport {
+    /// whether `nix flake check` as a whole succeeded.
+    pub success: bool,
+    /// every attribute of the `checks` output nix looked at, in order.
+    pub checks: Vec<CheckResult>,
+    /// errors that don't belong to a single check, e.g. from a broken
+    /// `devShells` output, or nix's closing summary. Also says why build
+    /// failures couldn't be attributed to their checks, if they couldn't.
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckResult {
+    pub system: String,
+    pub name: String,
+    /// the first error nix reported while evaluating or building this check.
+    pub error: Option<String>,
+}
+
+impl CheckResult {
+    /// e.g. `checks.x86_64-linux.fmt`.
+    pub fn attr_path(&self) -> String {
+        format!(
+            "checks.{}.{}",
+            quote_attr(&self.system),
+            quote_attr(&self.name)
+        )
+    }
+
+    pub fn passed(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+impl CheckReport {
+    pub fn failed(&self) -> impl Iterator<Item = &CheckResult> {
+        self.checks.iter().filter(|check| !check.passed())
+    }
+
+    /// Build a report from the log events of a `nix flake check` run.
+    /// Evaluation errors name the check they belong to; build failures are
+    /// left in `errors` until [`CheckReport::attribute_build_failures`].
+    fn from_events(events: &[NixLogEvent], success: bool) -> Self {
+        let mut report = CheckReport {
+            success,
+            ..CheckReport::default()
+        };
+        for event in events {
+            match event {
+                NixLogEvent::Start { text, .. } => {
+                    let text = strip_ansi(text);
+                    let Some(attr) = text.strip_prefix("checking derivation ") else {
+                        continue;
+                    };
+                    if let Some((system, name)) = check_attr(attr) {
+                        report.check_mut(&system, &name);
+                    }
+                }
+                NixLogEvent::Msg {
+                    level: Verbosity::Error,
+                    msg,
+                } => {
+                    let msg = strip_ansi(msg);
+                    let check = quoted(&msg).find_map(check_attr);
+                    match check {
+                        Some((system, name)) => report.fail(&system, &name, msg),
+                        None => report.errors.push(msg),
+                    }
+                }
+                _ => {}
+            }
+        }
+        report
+    }
+
+    fn has_build_failures(&self) -> bool {
+        self.errors.iter().any(|msg| failed_drv(msg).is_some())
+    }
+
+    /// Move build failures out of `errors` to the check whose derivation
+    /// they name. `drv_paths` is the output of evaluating [`DRV_PATHS`].
+    fn attribute_build_failures(&mut self, drv_paths: &DrvPaths) {
+        let by_drv: BTreeMap<&str, (&str, &str)> = drv_paths
+            .iter()
+            .flat_map(|(system, checks)| {
+                checks.iter().filter_map(move |(name, drv)| {
+                    Some((drv.as_deref()?, (system.as_str(), name.as_str())))
+                })
+            })
+            .collect();
+
+        for msg in std::mem::take(&mut self.errors) {
+            match failed_drv(&msg).and_then(|drv| by_drv.get(drv)) {
+                Some((system, name)) => self.fail(system, name, msg),
+                None => self.errors.push(msg),
+            }
+        }
+    }
+
+    fn check_mut(&mut self, system: &str, name: &str) -> &mut CheckResult {
+        let index = match self
+            .checks
+            .iter()
+            .position(|check| check.system == system && check.name == name)
+        {
+            Some(index) => index,
+            None => {
+                self.checks.push(CheckResult {
+                    system: system.to_string(),
+                    name: name.to_string(),
+                    error: None,
+                });
+                self.checks.len() - 1
+            }
+        };
+        &mut self.checks[index]
+    }
+
+    fn fail(&mut self, system: &str, name: &str, msg: String) {
+        self.check_mut(system, name).error.get_or_insert(msg);
+    }
+}
+
+/// `checks.<system>.<name>`, optionally quoted, split into system and name.
+/// Names with dots in them are in double quotes, e.g.
+/// `checks.x86_64-linux."python3.11"`.
+fn check_attr(attr: &str) -> Option<(String, String)> {
+    match <[String; 3]>::try_from(split_attr_path(attr.trim_matches('\''))) {
+        Ok([checks, system, name])
+            if checks == "checks" && !system.is_empty() && !name.is_empty() =>
+        {
+            Some((system, name))
+        }
+        _ => None,
+    }
+}
+
+/// Everything between single quotes in an error message.
+fn quoted(msg: &str) -> impl Iterator<Item = &str> {
+    msg.split('\'').skip(1).step_by(2)
+}
+
+/// The derivation an error message is about, e.g. `builder for
+/// '/nix/store/...-tests.drv' failed with exit code 1`.
+fn failed_drv(msg: &str) -> Option<&str> {
+    quoted(msg)
+        .map(|path| path.split('^').next().unwrap_or(path))
+        .find(|path| path.starts_with('/') && path.ends_with(".drv"))
+}
+
+/// `nix flake check` failing is a result, not an error.
+fn check_succeeded(result: Result<Vec<u8>, FlakeShowError>) -> Result<bool, FlakeShowError> {
+    match result {
+        Ok(_) => Ok(true),
+        Err(FlakeShowError::NonZeroExit { .. }) => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct NixFlakeCheckBuilder {
+    url: Option<FlakeRef>,
+    no_build: bool,
+    keep_going: bool,
+    all_systems: bool,
+    common: CommonArgs,
+}
+
+impl NixFlakeCheckBuilder {
+    common_setters!();
+
+    pub fn url(mut self, url: impl Into<FlakeRef>) -> Self {
+        self.url = Some(url.into());
+        self
+    }
+
+    /// Only evaluate the checks, don't build them.
+    pub fn no_build(mut self, no_build: bool) -> Self {
+        self.no_build = no_build;
+        self
+    }
+
+    /// Carry on after a check fails, so every failure ends up in the report.
+    pub fn keep_going(mut self, keep_going: bool) -> Self {
+        self.keep_going = keep_going;
+        self
+    }
+
+    pub fn all_systems(mut self, all_systems: bool) -> Self {
+        self.all_systems = all_systems;
+        self
+    }
+
+    /// Run the checks, returning a report whether they pass or not. Errors
+    /// are only returned if nix couldn't be run at all.
+    pub fn into_report(mut self) -> Result<CheckReport, FlakeShowError> {
+        let (events, drv_paths_cmd) = self.prepare();
+        let runner = self.common.take_runner();
+        let drv_paths_runner = runner.sibling();
+
+        let success = check_succeeded(runner.run(&self.build()))?;
+        let mut report = CheckReport::from_events(&events.lock().unwrap(), success);
+        if report.has_build_failures() {
+            let drv_paths = drv_paths_runner.run(&drv_paths_cmd).and_then(|json| {
+                serde_json::from_slice::<DrvPaths>(&json)
+                    .map_err(|err| FlakeShowError::json(err, &json))
+            });
+            match drv_paths {
+                Ok(drv_paths) => report.attribute_build_failures(&drv_paths),
+                Err(err) => report.errors.push(format!(
+                    "couldn't attribute build failures to checks: {err}"
+                )),
+            }
+        }
+        Ok(report)
+    }
+
+    /// Collect log events for the report, and prepare the command that
+    /// maps derivations back to checks.
+    fn prepare(&mut self) -> (Arc<Mutex<Vec<NixLogEvent>>>, Command) {
+        let events = self.common.collect_log_events();
+
+        let flake = self.url.clone().unwrap_or_else(|| FlakeRef::path("."));
+        let mut drv_paths_cmd = self.common.command(&["eval", "--json"]);
+        drv_paths_cmd
+            .arg(Installable::flake_attr(flake, &["checks"]).as_str())
+            .arg("--apply")
+            .arg(DRV_PATHS);
+        self.common.push_flags(&mut drv_paths_cmd);
+
+        (events, drv_paths_cmd)
+    }
+
+    pub fn build(self) -> Command {
+        let mut cmd = self.common.command(&["flake", "check"]);
+
+        if let Some(url) = self.url {
+            cmd.arg(url.to_string());
+        }
+
+        if self.no_build {
+            cmd.arg("--no-build");
+        }
+
+        if self.keep_going {
+            cmd.arg("--keep-going");
+        }
+
+        if self.all_systems {
+            cmd.arg("--all-systems");
+        }
+
+        self.common.push_flags(&mut cmd);
+        cmd
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{flake_check, NixOutput, ReplayBackend};
+
+    const CHECK_LOG: &str = r#"@nix {"action":"start","id":1,"level":3,"parent":0,"text":"checking flake output 'checks'","type":0}
+@nix {"action":"start","id":2,"level":3,"parent":1,"text":"checking derivation checks.x86_64-linux.fmt","type":0}
+@nix {"action":"stop","id":2}
+@nix {"action":"start","id":3,"level":3,"parent":1,"text":"checking derivation checks.x86_64-linux.tests","type":0}
+@nix {"action":"stop","id":3}
+@nix {"action":"start","id":4,"level":3,"parent":1,"text":"checking derivation checks.x86_64-linux.broken","type":0}
+@nix {"action":"msg","level":0,"msg":"\u001b[31;1merror:\u001b[0m\n       … while checking the derivation '\u001b[35;1mchecks.x86_64-linux.broken\u001b[0m'\n\n       error: attribute 'missing' missing"}
+@nix {"action":"stop","id":4}
+@nix {"action":"start","id":5,"level":3,"parent":0,"text":"running 2 flake checks","type":0}
+@nix {"action":"msg","level":0,"msg":"\u001b[31;1merror:\u001b[0m builder for '\u001b[35;1m/nix/store/a1-tests.drv\u001b[0m' failed with exit code 1"}
+@nix {"action":"stop","id":5}
+@nix {"action":"msg","level":0,"msg":"\u001b[31;1merror:\u001b[0m some errors were encountered during the evaluation"}
+"#;
+
+    #[test]
+    fn report_from_logs() {
+        let mut args: Vec<String> = ["flake", "check", "--keep-going", "--log-format"]
+            .map(String::from)
+            .into();
+        args.push("internal-json".into());
+        let backend = ReplayBackend::new()
+            .record(args, NixOutput::failure(1, CHECK_LOG))
+            .record(
+                ["eval", "--json", ".#checks", "--apply", DRV_PATHS],
+                NixOutput::success(
+                    r#"{"x86_64-linux": {"fmt": "/nix/store/f1-fmt.drv", "tests": "/nix/store/a1-tests.drv", "broken": null}}"#,
+                ),
+            );
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let report = flake_check()
+            .keep_going(true)
+            .log_events_to(tx)
+            .backend(backend)
+            .into_report()
+            .unwrap();
+
+        assert!(!report.success);
+        assert_eq!(rx.iter().count(), 12);
+        assert_eq!(
+            report
+                .checks
+                .iter()
+                .map(|check| (check.attr_path(), check.passed()))
+                .collect::<Vec<_>>(),
+            [
+                ("checks.x86_64-linux.fmt".to_string(), true),
+                ("checks.x86_64-linux.tests".to_string(), false),
+                ("checks.x86_64-linux.broken".to_string(), false),
+            ]
+        );
+        assert!(report.checks[1]
+            .error
+            .as_deref()
+            .unwrap()
+            .contains("failed with exit code 1"));
+        assert!(report.checks[2]
+            .error
+            .as_deref()
+            .unwrap()
+            .ends_with("error: attribute 'missing' missing"));
+        assert_eq!(
+            report.errors,
+            ["error: some errors were encountered during the evaluation"]
+        );
+    }
+
+    #[test]
+    fn dotted_check_names() {
+        assert_eq!(
+            check_attr("'checks.x86_64-linux.\"python3.11\"'"),
+            Some(("x86_64-linux".to_string(), "python3.11".to_string()))
+        );
+        assert_eq!(check_attr("checks.x86_64-linux"), None);
+        assert_eq!(check_attr("packages.x86_64-linux.hello"), None);
+
+        let check = CheckResult {
+            system: "x86_64-linux".to_string(),
+            name: "python3.11".to_string(),
+            error: None,
+        };
+        assert_eq!(check.attr_path(), "checks.x86_64-linux.\"python3.11\"");
+    }
+
+    #[test]
+    fn unattributed_build_failures() {
+        let log = r#"@nix {"action":"start","id":2,"level":3,"parent":0,"text":"checking derivation checks.x86_64-linux.tests","type":0}
+@nix {"action":"msg","level":0,"msg":"error: builder for '/nix/store/a1-tests.drv' failed with exit code 1"}
+"#;
+        let backend = ReplayBackend::new()
+            .record(
+                ["eval", "--json", ".#checks", "--apply", DRV_PATHS],
+                NixOutput::failure(1, "error: attribute 'checks' missing"),
+            )
+            .fallback(NixOutput::failure(1, log));
+
+        let report = flake_check().backend(backend).into_report().unwrap();
+
+        assert!(report.checks[0].passed());
+        assert_eq!(report.errors.len(), 2);
+        assert!(report.errors[0].contains("failed with exit code 1"));
+        assert!(report.errors[1].starts_with("couldn't attribute build failures to checks"));
+    }
+
+    #[test]
+    fn passing_checks() {
+        let report = flake_check()
+            .no_build(true)
+            .backend(ReplayBackend::new().fallback(NixOutput::success("").with_stderr(
+                r#"@nix {"action":"start","id":2,"level":3,"parent":0,"text":"checking derivation checks.aarch64-darwin.fmt","type":0}"#,
+            )))
+            .into_report()
+            .unwrap();
+
+        assert!(report.success);
+        assert_eq!(report.checks.len(), 1);
+        assert_eq!(report.failed().count(), 0);
+    }
+}
@@ -0,0 +1,215 @@
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::log::NixLogEventHandler;
+use crate::{
+    FlakeShowError, NixBackend, NixBinary, NixFlakeLogFormat, NixInvocation, NixLogEvent,
+    ProcessBackend, StderrLineHandler,
+};
+
+/// Flags and execution settings shared by every builder in this crate.
+#[derive(Default, Debug)]
+pub(crate) struct CommonArgs {
+    pub(crate) impure: bool,
+    pub(crate) recreate_lock_file: bool,
+    pub(crate) debug: bool,
+    pub(crate) refresh: bool,
+    pub(crate) verbosity_level: usize,
+    pub(crate) log_format: Option<NixFlakeLogFormat>,
+    pub(crate) nix: Option<NixBinary>,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) log_events: Option<NixLogEventHandler>,
+    pub(crate) backend: Option<Arc<dyn NixBackend>>,
+}
+
+impl CommonArgs {
+    /// `nix <subcommand>`, with stdout piped.
+    pub(crate) fn command(&self, subcommand: &[&str]) -> Command {
+        let mut cmd = self.nix.clone().unwrap_or_default().command();
+        cmd.args(subcommand);
+        cmd.stdout(Stdio::piped());
+        cmd.stderr(Stdio::inherit());
+        cmd
+    }
+
+    /// Append the shared flags, after the subcommand's own.
+    pub(crate) fn push_flags(&self, cmd: &mut Command) {
+        if self.impure {
+            cmd.arg("--impure");
+        }
+
+        if self.recreate_lock_file {
+            cmd.arg("--recreate-lock-file");
+        }
+
+        if self.refresh {
+            cmd.arg("--refresh");
+        }
+
+        if self.debug {
+            cmd.arg("--debug");
+        }
+
+        if self.verbosity_level > 0 {
+            cmd.arg(format!("-{}", "v".repeat(self.verbosity_level)));
+        }
+
+        if let Some(log_format) = &self.log_format {
+            cmd.arg("--log-format");
+            let log_format_str = match log_format {
+                NixFlakeLogFormat::Raw => "raw",
+                NixFlakeLogFormat::InternalJson => "internal-json",
+                NixFlakeLogFormat::Bar => "bar",
+                NixFlakeLogFormat::BarWithLogs => "bar-with-logs",
+            };
+            cmd.arg(log_format_str);
+        }
+    }
+
+    /// Keep every log event nix emits for the builder to interpret once it
+    /// finishes, still passing them on to a handler set with `on_log_event`.
+    pub(crate) fn collect_log_events(&mut self) -> Arc<Mutex<Vec<NixLogEvent>>> {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&events);
+        let mut forward = self.log_events.take();
+        self.log_events = Some(NixLogEventHandler(Box::new(move |event| {
+            if let Some(forward) = &mut forward {
+                (forward.0)(event.clone());
+            }
+            sink.lock().unwrap().push(event);
+        })));
+        events
+    }
+
+    /// Split off what's needed to execute the command. Must be called
+    /// before the command is built, as a log event handler switches nix to
+    /// [`NixFlakeLogFormat::InternalJson`].
+    pub(crate) fn take_runner(&mut self) -> Runner {
+        let on_line = self.log_events.take().map(|handler| {
+            self.log_format = Some(NixFlakeLogFormat::InternalJson);
+            handler.into_line_handler()
+        });
+        Runner {
+            backend: self.backend.take(),
+            timeout: self.timeout,
+            on_line,
+        }
+    }
+}
+
+/// Executes a built command through the configured [`NixBackend`].
+pub(crate) struct Runner {
+    backend: Option<Arc<dyn NixBackend>>,
+    timeout: Option<Duration>,
+    on_line: Option<StderrLineHandler>,
+}
+
+impl Runner {
+    /// A runner for a follow-up command, going through the same backend
+    /// with the same timeout, but without the log event handler.
+    pub(crate) fn sibling(&self) -> Runner {
+        Runner {
+            backend: self.backend.clone(),
+            timeout: self.timeout,
+            on_line: None,
+        }
+    }
+
+    pub(crate) fn run(self, cmd: &Command) -> Result<Vec<u8>, FlakeShowError> {
+        let invocation = NixInvocation::from_command(cmd, self.timeout);
+        match self.backend {
+            Some(backend) => backend.run(&invocation, self.on_line),
+            None => ProcessBackend.run(&invocation, self.on_line),
+        }
+    }
+
+    /// Like [`Runner::run`], but without blocking the executor. A custom
+    /// [`NixBackend`] is run on tokio's blocking thread pool.
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn run_async(self, cmd: Command) -> Result<Vec<u8>, FlakeShowError> {
+        let invocation = NixInvocation::from_command(&cmd, self.timeout);
+        let on_line = self.on_line;
+        match self.backend {
+            Some(backend) => tokio::task::spawn_blocking(move || backend.run(&invocation, on_line))
+                .await
+                .unwrap_or_else(|err| std::panic::resume_unwind(err.into_panic())),
+            None => crate::process::run_captured_async(cmd, self.timeout, on_line).await,
+        }
+    }
+}
+
+/// The setters for [`CommonArgs`], for a builder with a `common` field.
+macro_rules! common_setters {
+    () => {
+        pub fn impure(mut self, impure: bool) -> Self {
+            self.common.impure = impure;
+            self
+        }
+
+        pub fn recreate_lock_file(mut self, recreate_lock_file: bool) -> Self {
+            self.common.recreate_lock_file = recreate_lock_file;
+            self
+        }
+
+        pub fn refresh(mut self, refresh: bool) -> Self {
+            self.common.refresh = refresh;
+            self
+        }
+
+        pub fn debug(mut self, debug: bool) -> Self {
+            self.common.debug = debug;
+            self
+        }
+
+        pub fn verbosity_level(mut self, verbosity_level: usize) -> Self {
+            self.common.verbosity_level = verbosity_level;
+            self
+        }
+
+        pub fn log_format(mut self, log_format: Option<$crate::NixFlakeLogFormat>) -> Self {
+            self.common.log_format = log_format;
+            self
+        }
+
+        /// Run this command with a specific nix binary instead of the discovered one.
+        pub fn nix_binary(mut self, nix: $crate::NixBinary) -> Self {
+            self.common.nix = Some(nix);
+            self
+        }
+
+        /// Kill nix (and everything it spawned) if it hasn't finished after
+        /// `timeout`, failing with [`FlakeShowError::Timeout`](crate::FlakeShowError::Timeout).
+        pub fn timeout(mut self, timeout: std::time::Duration) -> Self {
+            self.common.timeout = Some(timeout);
+            self
+        }
+
+        /// Call `on_event` with every [`NixLogEvent`](crate::NixLogEvent) while nix
+        /// runs. This switches the log format to
+        /// [`NixFlakeLogFormat::InternalJson`](crate::NixFlakeLogFormat::InternalJson).
+        pub fn on_log_event(
+            mut self,
+            on_event: impl FnMut($crate::NixLogEvent) + Send + 'static,
+        ) -> Self {
+            self.common.log_events = Some($crate::log::NixLogEventHandler(Box::new(on_event)));
+            self
+        }
+
+        /// Like `on_log_event`, but sends every event down a channel. Events
+        /// are dropped once the receiver hangs up.
+        pub fn log_events_to(self, events: std::sync::mpsc::Sender<$crate::NixLogEvent>) -> Self {
+            self.on_log_event(move |event| {
+                let _ = events.send(event);
+            })
+        }
+
+        /// Execute nix through `backend` instead of spawning it, e.g. a
+        /// [`ReplayBackend`](crate::ReplayBackend) serving recorded output in tests.
+        pub fn backend(mut self, backend: impl $crate::NixBackend + 'static) -> Self {
+            self.common.backend = Some(std::sync::Arc::new(backend));
+            self
+        }
+    };
+}
+pub(crate) use common_setters;
@@ -0,0 +1,259 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::process::Command;
+
+use serde::Deserialize;
+
+use crate::common::{common_setters, CommonArgs};
+use crate::{Derivation, FlakeRef, FlakeShowError, Installable};
+
+/// Variables `nix develop` keeps from the calling environment rather than
+/// taking them from the dev shell.
+const IGNORED_VARIABLES: &[&str] = &[
+    "BASHOPTS",
+    "HOME",
+    "NIX_BUILD_TOP",
+    "NIX_ENFORCE_PURITY",
+    "NIX_LOG_FD",
+    "NIX_REMOTE",
+    "PPID",
+    "SHELL",
+    "SHELLOPTS",
+    "SSL_CERT_FILE",
+    "NIX_SSL_CERT_FILE",
+    "TEMP",
+    "TEMPDIR",
+    "TERM",
+    "TMP",
+    "TMPDIR",
+    "TZ",
+    "UID",
+];
+
+/// The environment of a dev shell, as printed by `nix print-dev-env --json`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DevEnv {
+    #[serde(default)]
+    pub variables: BTreeMap<String, DevEnvVariable>,
+    /// from function name to its body, e.g. `genericBuild` or the phases.
+    #[serde(default)]
+    pub bash_functions: BTreeMap<String, String>,
+    /// from file name (`.attrs.json`, `.attrs.sh`) to its contents, for
+    /// shells of derivations using `__structuredAttrs`.
+    #[serde(default)]
+    pub structured_attrs: BTreeMap<String, String>,
+}
+
+/// A shell variable of a [`DevEnv`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+pub enum DevEnvVariable {
+    /// an environment variable, visible to every process in the shell.
+    Exported(String),
+    /// a plain shell variable, only visible to bash itself.
+    Var(String),
+    Array(Vec<String>),
+    Associative(BTreeMap<String, String>),
+    /// a variable nix couldn't represent.
+    #[serde(other)]
+    Unknown,
+}
+
+impl DevEnv {
+    pub fn from_stdout(v: &[u8]) -> Result<Self, FlakeShowError> {
+        serde_json::from_slice(v).map_err(|err| FlakeShowError::json(err, v))
+    }
+
+    /// The variables a process started in this shell would see, leaving
+    /// out the ones `nix develop` takes from the calling environment.
+    pub fn exported(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.variables
+            .iter()
+            .filter(|(name, _)| !IGNORED_VARIABLES.contains(&name.as_str()))
+            .filter_map(|(name, var)| match var {
+                DevEnvVariable::Exported(value) => Some((name.as_str(), value.as_str())),
+                _ => None,
+            })
+    }
+
+    /// Set up `cmd` to run inside this shell, the way `nix develop -c`
+    /// would: exported variables are set and the shell's `PATH` is put in
+    /// front of the current one. Bash functions and hooks aren't run.
+    ///
+    /// The current `PATH` is the one `cmd` would run with: its own if it
+    /// sets one, otherwise this process's, like `nix develop` keeps the
+    /// `PATH` it was started with. Removing `PATH` from `cmd` leaves only
+    /// the shell's. After [`Command::env_clear`], which can't be told from
+    /// the outside, set `PATH` on `cmd` first, empty for none.
+    pub fn apply_to<'a>(&self, cmd: &'a mut Command) -> &'a mut Command {
+        for (name, value) in self.exported() {
+            if name == "PATH" {
+                // `nix develop` appends the `PATH` it inherited
+                let inherited = cmd
+                    .get_envs()
+                    .find(|(key, _)| *key == "PATH")
+                    .map(|(_, value)| value.map(OsString::from))
+                    .unwrap_or_else(|| std::env::var_os("PATH"));
+
+                let mut path = OsString::from(value);
+                if let Some(inherited) = inherited.filter(|p| !p.is_empty()) {
+                    path.push(":");
+                    path.push(inherited);
+                }
+                cmd.env("PATH", path);
+            } else {
+                cmd.env(name, value);
+            }
+        }
+        cmd
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct NixPrintDevEnvBuilder {
+    installable: Option<Installable>,
+    json: bool,
+    common: CommonArgs,
+}
+
+impl NixPrintDevEnvBuilder {
+    common_setters!();
+
+    /// The shell to print, nix defaults to the flake in the current
+    /// directory.
+    pub fn installable(mut self, installable: impl Into<Installable>) -> Self {
+        self.installable = Some(installable.into());
+        self
+    }
+
+    /// A dev shell of `flake`, as reported by [`crate::flake_show`].
+    pub fn dev_shell(self, flake: impl Into<FlakeRef>, dev_shell: &Derivation) -> Self {
+        self.installable(Installable::derivation(flake, dev_shell))
+    }
+
+    pub fn json(mut self, json: bool) -> Self {
+        self.json = json;
+        self
+    }
+
+    pub fn into_dev_env(mut self) -> Result<DevEnv, FlakeShowError> {
+        let runner = self.common.take_runner();
+        let stdout = runner.run(&self.json(true).build())?;
+        DevEnv::from_stdout(&stdout)
+    }
+
+    pub fn build(self) -> Command {
+        let mut cmd = self.common.command(&["print-dev-env"]);
+
+        if let Some(installable) = &self.installable {
+            cmd.arg(installable.as_str());
+        }
+
+        if self.json {
+            cmd.arg("--json");
+        }
+
+        self.common.push_flags(&mut cmd);
+        cmd
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{print_dev_env, FlakeInfo, NixOutput, ReplayBackend};
+
+    const DEV_ENV: &str = r#"{
+        "bashFunctions": {"runHook": "local hookName=\"$1\"; ..."},
+        "variables": {
+            "CARGO": {"type": "exported", "value": "/nix/store/x-cargo/bin/cargo"},
+            "HOME": {"type": "exported", "value": "/homeless-shelter"},
+            "PATH": {"type": "exported", "value": "/nix/store/x-cargo/bin"},
+            "out": {"type": "var", "value": "/nix/store/y-nix-shell"},
+            "buildInputs": {"type": "array", "value": ["/nix/store/x-cargo"]},
+            "hooks": {"type": "associative", "value": {"pre": "true"}},
+            "BASH_VERSINFO": {"type": "unknown"}
+        },
+        "structuredAttrs": {".attrs.json": "{}"}
+    }"#;
+
+    #[test]
+    fn parse_dev_env() {
+        let env = DevEnv::from_stdout(DEV_ENV.as_bytes()).unwrap();
+
+        assert_eq!(
+            env.variables["out"],
+            DevEnvVariable::Var("/nix/store/y-nix-shell".into())
+        );
+        assert_eq!(
+            env.variables["buildInputs"],
+            DevEnvVariable::Array(vec!["/nix/store/x-cargo".into()])
+        );
+        assert_eq!(
+            env.variables["hooks"],
+            DevEnvVariable::Associative([("pre".into(), "true".into())].into())
+        );
+        assert_eq!(env.variables["BASH_VERSINFO"], DevEnvVariable::Unknown);
+        assert!(env.bash_functions.contains_key("runHook"));
+        assert_eq!(env.structured_attrs[".attrs.json"], "{}");
+        assert_eq!(
+            env.exported().map(|(name, _)| name).collect::<Vec<_>>(),
+            ["CARGO", "PATH"]
+        );
+    }
+
+    #[test]
+    fn apply_to_command() {
+        let env = DevEnv::from_stdout(DEV_ENV.as_bytes()).unwrap();
+        let mut cmd = Command::new("cargo");
+        cmd.env("PATH", "/usr/bin");
+        env.apply_to(&mut cmd);
+
+        let envs: BTreeMap<_, _> = cmd
+            .get_envs()
+            .map(|(key, value)| (key.to_str().unwrap(), value.unwrap().to_str().unwrap()))
+            .collect();
+        assert_eq!(envs["PATH"], "/nix/store/x-cargo/bin:/usr/bin");
+        assert_eq!(envs["CARGO"], "/nix/store/x-cargo/bin/cargo");
+        assert!(!envs.contains_key("HOME"));
+        assert!(!envs.contains_key("out"));
+
+        // nothing to append once the inherited `PATH` is gone
+        let mut removed = Command::new("cargo");
+        removed.env_remove("PATH");
+        let mut cleared = Command::new("cargo");
+        cleared.env_clear().env("PATH", "");
+        for cmd in [&mut removed, &mut cleared] {
+            env.apply_to(cmd);
+            assert_eq!(
+                cmd.get_envs().find(|(key, _)| *key == "PATH"),
+                Some(("PATH".as_ref(), Some("/nix/store/x-cargo/bin".as_ref())))
+            );
+        }
+    }
+
+    #[test]
+    fn dev_env_of_a_shown_dev_shell() {
+        let info = FlakeInfo::from_stdout(
+            br#"{"devShells": {"x86_64-linux": {"default": {"name": "nix-shell", "type": "derivation"}}}}"#,
+        )
+        .unwrap();
+        let shell = &info.for_system("x86_64-linux").dev_shells[0];
+
+        let backend = ReplayBackend::new().record(
+            [
+                "print-dev-env",
+                "/src/app#devShells.x86_64-linux.default",
+                "--json",
+            ],
+            NixOutput::success(DEV_ENV),
+        );
+        let env = print_dev_env()
+            .dev_shell(FlakeRef::path("/src/app"), shell)
+            .backend(backend)
+            .into_dev_env()
+            .unwrap();
+        assert_eq!(env.variables.len(), 7);
+    }
+}
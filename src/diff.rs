@@ -0,0 +1,597 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::{Derivation, FlakeInfo, FlakeLock, LockNode};
+
+/// What changed between two [`FlakeInfo`]s, see [`FlakeInfo::diff`].
+///
+/// Derivations are matched up by [`Derivation::invocation`], templates by
+/// their name. Renders as a human readable summary through `Display`, and
+/// as JSON through [`FlakeInfoDiff::to_json`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FlakeInfoDiff {
+    /// from output (e.g. `packages`) to architecture to what changed there.
+    /// Outputs and architectures without changes are left out.
+    pub outputs: BTreeMap<String, BTreeMap<String, SystemDiff>>,
+    pub templates: TemplatesDiff,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SystemDiff {
+    pub added: Vec<DerivationSummary>,
+    pub removed: Vec<DerivationSummary>,
+    pub changed: Vec<DerivationChange>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DerivationSummary {
+    pub invocation: String,
+    pub name: String,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DerivationChange {
+    pub invocation: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<Change<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<Change<Option<String>>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Change<T> {
+    pub before: T,
+    pub after: T,
+}
+
+/// from template name to description.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplatesDiff {
+    pub added: BTreeMap<String, String>,
+    pub removed: BTreeMap<String, String>,
+    pub changed: BTreeMap<String, Change<String>>,
+}
+
+impl From<&Derivation> for DerivationSummary {
+    fn from(value: &Derivation) -> Self {
+        DerivationSummary {
+            invocation: value.invocation.clone(),
+            name: value.name.clone(),
+            description: value.description.clone(),
+        }
+    }
+}
+
+impl SystemDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl TemplatesDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl FlakeInfoDiff {
+    pub fn is_empty(&self) -> bool {
+        self.outputs.is_empty() && self.templates.is_empty()
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("diffs always serialize")
+    }
+}
+
+fn change<T: PartialEq + Clone>(before: &T, after: &T) -> Option<Change<T>> {
+    (before != after).then(|| Change {
+        before: before.clone(),
+        after: after.clone(),
+    })
+}
+
+fn diff_system(before: &[Derivation], after: &[Derivation]) -> SystemDiff {
+    let before: BTreeMap<_, _> = before.iter().map(|d| (d.invocation.as_str(), d)).collect();
+    let after: BTreeMap<_, _> = after.iter().map(|d| (d.invocation.as_str(), d)).collect();
+
+    let mut diff = SystemDiff::default();
+    for (invocation, old) in &before {
+        match after.get(invocation) {
+            None => diff.removed.push((*old).into()),
+            Some(new) => {
+                let name = change(&old.name, &new.name);
+                let description = change(&old.description, &new.description);
+                if name.is_some() || description.is_some() {
+                    diff.changed.push(DerivationChange {
+                        invocation: invocation.to_string(),
+                        name,
+                        description,
+                    });
+                }
+            }
+        }
+    }
+    for (invocation, new) in &after {
+        if !before.contains_key(invocation) {
+            diff.added.push((*new).into());
+        }
+    }
+    diff
+}
+
+fn diff_output(
+    before: &BTreeMap<String, Vec<Derivation>>,
+    after: &BTreeMap<String, Vec<Derivation>>,
+) -> BTreeMap<String, SystemDiff> {
+    let systems: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+    systems
+        .into_iter()
+        .map(|sys| {
+            let old = before.get(sys).map(Vec::as_slice).unwrap_or_default();
+            let new = after.get(sys).map(Vec::as_slice).unwrap_or_default();
+            (sys.clone(), diff_system(old, new))
+        })
+        .filter(|(_, diff)| !diff.is_empty())
+        .collect()
+}
+
+impl FlakeInfo {
+    /// Everything that changed going from `self` to `other`.
+    pub fn diff(&self, other: &FlakeInfo) -> FlakeInfoDiff {
+        let mut outputs = BTreeMap::new();
+        for (output, before, after) in [
+            ("packages", &self.packages, &other.packages),
+            ("devShells", &self.dev_shells, &other.dev_shells),
+            ("checks", &self.checks, &other.checks),
+            (
+                "legacyPackages",
+                &self.legacy_packages,
+                &other.legacy_packages,
+            ),
+        ] {
+            let systems = diff_output(before, after);
+            if !systems.is_empty() {
+                outputs.insert(output.to_string(), systems);
+            }
+        }
+
+        let mut templates = TemplatesDiff::default();
+        for (name, old) in &self.templates {
+            match other.templates.get(name) {
+                None => {
+                    templates.removed.insert(name.clone(), old.clone());
+                }
+                Some(new) => {
+                    if let Some(change) = change(old, new) {
+                        templates.changed.insert(name.clone(), change);
+                    }
+                }
+            }
+        }
+        for (name, new) in &other.templates {
+            if !self.templates.contains_key(name) {
+                templates.added.insert(name.clone(), new.clone());
+            }
+        }
+
+        FlakeInfoDiff { outputs, templates }
+    }
+}
+
+impl fmt::Display for FlakeInfoDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "no changes");
+        }
+
+        for (output, systems) in &self.outputs {
+            for (sys, diff) in systems {
+                writeln!(f, "{output}.{sys}:")?;
+                for added in &diff.added {
+                    writeln!(f, "  + {} ({})", added.invocation, added.name)?;
+                }
+                for removed in &diff.removed {
+                    writeln!(f, "  - {} ({})", removed.invocation, removed.name)?;
+                }
+                for changed in &diff.changed {
+                    if let Some(Change { before, after }) = &changed.name {
+                        writeln!(f, "  ~ {}: name {before} -> {after}", changed.invocation)?;
+                    }
+                    if let Some(Change { before, after }) = &changed.description {
+                        writeln!(
+                            f,
+                            "  ~ {}: description {:?} -> {:?}",
+                            changed.invocation,
+                            before.as_deref().unwrap_or_default(),
+                            after.as_deref().unwrap_or_default()
+                        )?;
+                    }
+                }
+            }
+        }
+
+        if !self.templates.is_empty() {
+            writeln!(f, "templates:")?;
+            for (name, description) in &self.templates.added {
+                writeln!(f, "  + {name}: {description}")?;
+            }
+            for (name, description) in &self.templates.removed {
+                writeln!(f, "  - {name}: {description}")?;
+            }
+            for (name, Change { before, after }) in &self.templates.changed {
+                writeln!(f, "  ~ {name}: description {before:?} -> {after:?}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What changed between two [`FlakeLock`]s, see [`FlakeLock::diff`].
+///
+/// Inputs are keyed by their input path, e.g. `nixpkgs` or
+/// `home-manager/nixpkgs`; inputs that follow another one are left out.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LockDiff {
+    pub added: BTreeMap<String, LockedInputSummary>,
+    pub removed: BTreeMap<String, LockedInputSummary>,
+    pub changed: BTreeMap<String, LockedInputChange>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LockedInputSummary {
+    /// the reference as written in `flake.nix`, e.g. `github:NixOS/nixpkgs/nixos-unstable`.
+    pub original: Option<String>,
+    pub rev: Option<String>,
+    /// seconds since the epoch.
+    pub last_modified: Option<u64>,
+    pub nar_hash: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LockedInputChange {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original: Option<Change<Option<String>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rev: Option<Change<Option<String>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<Change<Option<u64>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nar_hash: Option<Change<Option<String>>>,
+}
+
+impl From<&LockNode> for LockedInputSummary {
+    fn from(value: &LockNode) -> Self {
+        let locked = value.locked.as_ref();
+        LockedInputSummary {
+            original: value
+                .original
+                .as_ref()
+                .and_then(|original| original.to_flake_ref().ok())
+                .map(|original| original.to_string()),
+            rev: locked.and_then(|locked| locked.rev.clone()),
+            last_modified: locked.and_then(|locked| locked.last_modified),
+            nar_hash: locked.and_then(|locked| locked.nar_hash.clone()),
+        }
+    }
+}
+
+impl LockDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("diffs always serialize")
+    }
+}
+
+impl FlakeLock {
+    /// Every input that was added, removed or re-locked going from `self`
+    /// to `other`.
+    pub fn diff(&self, other: &FlakeLock) -> LockDiff {
+        let before = self.input_paths();
+        let after = other.input_paths();
+
+        let mut diff = LockDiff::default();
+        for (path, old) in &before {
+            let old = LockedInputSummary::from(*old);
+            match after.get(path) {
+                None => {
+                    diff.removed.insert(path.clone(), old);
+                }
+                Some(new) => {
+                    let new = LockedInputSummary::from(*new);
+                    let change = LockedInputChange {
+                        original: change(&old.original, &new.original),
+                        rev: change(&old.rev, &new.rev),
+                        last_modified: change(&old.last_modified, &new.last_modified),
+                        nar_hash: change(&old.nar_hash, &new.nar_hash),
+                    };
+                    if change.original.is_some()
+                        || change.rev.is_some()
+                        || change.last_modified.is_some()
+                        || change.nar_hash.is_some()
+                    {
+                        diff.changed.insert(path.clone(), change);
+                    }
+                }
+            }
+        }
+        for (path, new) in &after {
+            if !before.contains_key(path) {
+                diff.added.insert(path.clone(), (*new).into());
+            }
+        }
+        diff
+    }
+}
+
+/// A commit shortened the way git does.
+fn short_rev(rev: &Option<String>) -> &str {
+    match rev {
+        Some(rev) => rev.get(..7).unwrap_or(rev),
+        None => "-",
+    }
+}
+
+/// `YYYY-MM-DD` of a unix timestamp, in UTC.
+fn date(secs: &Option<u64>) -> String {
+    let Some(secs) = secs else {
+        return "-".to_string();
+    };
+    // Howard Hinnant's civil_from_days.
+    let z = (secs / 86400) as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+impl fmt::Display for LockDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "no changes");
+        }
+
+        for (path, input) in &self.added {
+            writeln!(
+                f,
+                "  + {path}: {} {} ({})",
+                input.original.as_deref().unwrap_or("-"),
+                short_rev(&input.rev),
+                date(&input.last_modified)
+            )?;
+        }
+        for (path, input) in &self.removed {
+            writeln!(
+                f,
+                "  - {path}: {}",
+                input.original.as_deref().unwrap_or("-")
+            )?;
+        }
+        for (path, change) in &self.changed {
+            if let Some(Change { before, after }) = &change.original {
+                writeln!(
+                    f,
+                    "  ~ {path}: {} -> {}",
+                    before.as_deref().unwrap_or("-"),
+                    after.as_deref().unwrap_or("-")
+                )?;
+            }
+            if let Some(Change { before, after }) = &change.rev {
+                writeln!(
+                    f,
+                    "  ~ {path}: rev {} -> {}",
+                    short_rev(before),
+                    short_rev(after)
+                )?;
+            }
+            if let Some(Change { before, after }) = &change.last_modified {
+                writeln!(
+                    f,
+                    "  ~ {path}: lastModified {} -> {}",
+                    date(before),
+                    date(after)
+                )?;
+            }
+            if change.rev.is_none() && change.last_modified.is_none() && change.nar_hash.is_some() {
+                writeln!(f, "  ~ {path}: narHash changed")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_snapshots() {
+        let before = FlakeInfo::from_stdout(
+            br#"{
+                "packages": {
+                    "x86_64-linux": {
+                        "hello": {"name": "hello-2.12", "type": "derivation"},
+                        "old": {"name": "old-1.0", "type": "derivation"},
+                        "same": {"name": "same-1.0", "type": "derivation"}
+                    }
+                },
+                "templates": {
+                    "rust": {"description": "Rust"},
+                    "gone": {"description": "Gone"}
+                }
+            }"#,
+        )
+        .unwrap();
+        let after = FlakeInfo::from_stdout(
+            br#"{
+                "packages": {
+                    "x86_64-linux": {
+                        "hello": {"name": "hello-2.13", "type": "derivation", "description": "says hi"},
+                        "same": {"name": "same-1.0", "type": "derivation"}
+                    },
+                    "aarch64-darwin": {
+                        "hello": {"name": "hello-2.13", "type": "derivation"}
+                    }
+                },
+                "devShells": {"x86_64-linux": {}},
+                "templates": {
+                    "rust": {"description": "Rust, now with clippy"},
+                    "python": {"description": "Python"}
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let diff = before.diff(&after);
+        assert_eq!(diff.outputs.keys().collect::<Vec<_>>(), ["packages"]);
+        let linux = &diff.outputs["packages"]["x86_64-linux"];
+        assert!(linux.added.is_empty());
+        assert_eq!(linux.removed[0].invocation, "old");
+        assert_eq!(linux.changed[0].invocation, "hello");
+        assert_eq!(
+            linux.changed[0].name,
+            Some(Change {
+                before: "hello-2.12".to_string(),
+                after: "hello-2.13".to_string()
+            })
+        );
+        assert_eq!(diff.outputs["packages"]["aarch64-darwin"].added.len(), 1);
+        assert_eq!(diff.templates.added["python"], "Python");
+        assert_eq!(diff.templates.removed["gone"], "Gone");
+        assert_eq!(
+            diff.templates.changed["rust"].after,
+            "Rust, now with clippy"
+        );
+
+        assert_eq!(
+            diff.to_string(),
+            "packages.aarch64-darwin:
+  + hello (hello-2.13)
+packages.x86_64-linux:
+  - old (old-1.0)
+  ~ hello: name hello-2.12 -> hello-2.13
+  ~ hello: description \"\" -> \"says hi\"
+templates:
+  + python: Python
+  - gone: Gone
+  ~ rust: description \"Rust\" -> \"Rust, now with clippy\"
+"
+        );
+
+        let json = diff.to_json();
+        assert_eq!(
+            json["outputs"]["packages"]["x86_64-linux"]["removed"][0]["name"],
+            "old-1.0"
+        );
+        assert!(before.diff(&before).is_empty());
+    }
+
+    #[test]
+    fn lock_diff() {
+        let node = |rev: &str, last_modified: u64| {
+            format!(
+                r#"{{"locked": {{"type": "github", "owner": "NixOS", "repo": "nixpkgs", "rev": "{rev}", "lastModified": {last_modified}, "narHash": "sha256-{rev}="}},
+                    "original": {{"type": "github", "owner": "NixOS", "repo": "nixpkgs", "ref": "nixos-unstable"}}}}"#
+            )
+        };
+        let before = FlakeLock::parse(
+            format!(
+                r#"{{"nodes": {{
+                    "root": {{"inputs": {{"nixpkgs": "nixpkgs", "old": "old", "same": "same"}}}},
+                    "nixpkgs": {},
+                    "old": {},
+                    "same": {}
+                }}, "root": "root", "version": 7}}"#,
+                node("1111111aaaaaaa", 1709251200),
+                node("2222222bbbbbbb", 1709251200),
+                node("3333333ccccccc", 1709251200),
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        let after = FlakeLock::parse(
+            format!(
+                r#"{{"nodes": {{
+                    "root": {{"inputs": {{"nixpkgs": "nixpkgs", "new": "new", "same": "same"}}}},
+                    "nixpkgs": {},
+                    "new": {},
+                    "same": {}
+                }}, "root": "root", "version": 7}}"#,
+                node("4444444ddddddd", 1710028800),
+                node("5555555eeeeeee", 1710028800),
+                node("3333333ccccccc", 1709251200),
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+
+        let diff = before.diff(&after);
+        assert_eq!(diff.added.keys().collect::<Vec<_>>(), ["new"]);
+        assert_eq!(diff.removed.keys().collect::<Vec<_>>(), ["old"]);
+        assert_eq!(diff.changed.keys().collect::<Vec<_>>(), ["nixpkgs"]);
+        let nixpkgs = &diff.changed["nixpkgs"];
+        assert!(nixpkgs.original.is_none());
+        assert_eq!(
+            nixpkgs.last_modified,
+            Some(Change {
+                before: Some(1709251200),
+                after: Some(1710028800)
+            })
+        );
+        assert!(nixpkgs.nar_hash.is_some());
+
+        assert_eq!(
+            diff.to_string(),
+            "  + new: github:NixOS/nixpkgs/nixos-unstable 5555555 (2024-03-10)
+  - old: github:NixOS/nixpkgs/nixos-unstable
+  ~ nixpkgs: rev 1111111 -> 4444444
+  ~ nixpkgs: lastModified 2024-03-01 -> 2024-03-10
+"
+        );
+        assert_eq!(
+            diff.to_json()["changed"]["nixpkgs"]["rev"]["after"],
+            "4444444ddddddd"
+        );
+        let json = diff.to_json();
+        assert_eq!(
+            json["changed"]["nixpkgs"]["lastModified"]["after"],
+            1710028800
+        );
+        assert!(json["changed"]["nixpkgs"]["narHash"].is_object());
+        let added = json["added"]["new"].as_object().unwrap();
+        assert_eq!(
+            added.keys().collect::<Vec<_>>(),
+            ["lastModified", "narHash", "original", "rev"]
+        );
+        assert!(after.diff(&after).is_empty());
+    }
+
+    #[test]
+    fn short_revs_of_untrusted_locks() {
+        assert_eq!(short_rev(&Some("4444444ddddddd".into())), "4444444");
+        assert_eq!(short_rev(&Some("abc".into())), "abc");
+        // byte 7 is inside the `é`
+        assert_eq!(short_rev(&Some("abcdeféé".into())), "abcdeféé");
+        assert_eq!(short_rev(&None), "-");
+    }
+}
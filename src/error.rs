@@ -0,0 +1,150 @@
+use std::fmt;
+
+/// Everything that can go wrong while running nix and interpreting its output.
+#[derive(Debug)]
+pub enum FlakeShowError {
+    /// No nix binary could be found to spawn.
+    NixNotFound,
+    /// nix was found but could not be spawned or waited on.
+    Spawn(std::io::Error),
+    /// nix ran, but exited unsuccessfully.
+    NonZeroExit {
+        /// `None` when the process was terminated by a signal.
+        code: Option<i32>,
+        stderr: String,
+    },
+    /// nix didn't finish within the configured timeout and was killed.
+    Timeout {
+        timeout: std::time::Duration,
+        /// whatever nix printed to stderr before it was killed.
+        stderr: String,
+    },
+    /// nix printed something that doesn't match the expected JSON shape.
+    Json {
+        source: serde_json::Error,
+        /// byte offset into the output where decoding failed.
+        offset: usize,
+    },
+    /// A file such as `flake.lock` couldn't be read.
+    Io(std::io::Error),
+    /// A `flake.lock` uses a format version this crate doesn't understand.
+    UnsupportedLockVersion(u32),
+    /// Serialized flake info uses a schema version this crate doesn't
+    /// understand.
+    UnsupportedSchemaVersion(u32),
+    /// A [`crate::ReplayBackend`] was asked to run something it has no
+    /// recording for.
+    MissingRecording { args: Vec<String> },
+    /// A string couldn't be parsed as a [`crate::FlakeRef`].
+    InvalidFlakeRef { input: String, reason: &'static str },
+    /// nix printed output that isn't valid UTF-8.
+    Utf8(std::string::FromUtf8Error),
+    /// Evaluating derivation meta returned a different number of entries
+    /// than derivations were asked for.
+    MetaCountMismatch { expected: usize, got: usize },
+}
+
+impl FlakeShowError {
+    pub(crate) fn spawn(err: std::io::Error) -> Self {
+        if err.kind() == std::io::ErrorKind::NotFound {
+            FlakeShowError::NixNotFound
+        } else {
+            FlakeShowError::Spawn(err)
+        }
+    }
+
+    pub(crate) fn json(source: serde_json::Error, input: &[u8]) -> Self {
+        let offset = byte_offset(input, source.line(), source.column());
+        FlakeShowError::Json { source, offset }
+    }
+}
+
+/// serde_json reports 1-based lines and byte columns, convert that back
+/// into an offset into the original input.
+fn byte_offset(input: &[u8], line: usize, column: usize) -> usize {
+    let line_start: usize = input
+        .split(|&b| b == b'\n')
+        .take(line.saturating_sub(1))
+        .map(|line| line.len() + 1)
+        .sum();
+    (line_start + column.saturating_sub(1)).min(input.len())
+}
+
+impl fmt::Display for FlakeShowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlakeShowError::NixNotFound => write!(f, "could not find a nix binary"),
+            FlakeShowError::Spawn(err) => write!(f, "failed to run nix: {err}"),
+            FlakeShowError::NonZeroExit { code, stderr } => {
+                match code {
+                    Some(code) => write!(f, "nix exited with status {code}")?,
+                    None => write!(f, "nix was terminated by a signal")?,
+                }
+                if !stderr.trim().is_empty() {
+                    write!(f, ": {}", stderr.trim())?;
+                }
+                Ok(())
+            }
+            FlakeShowError::Timeout { timeout, stderr } => {
+                write!(f, "nix did not finish within {timeout:?}")?;
+                if !stderr.trim().is_empty() {
+                    write!(f, ": {}", stderr.trim())?;
+                }
+                Ok(())
+            }
+            FlakeShowError::Json { source, offset } => {
+                write!(f, "failed to decode nix output at byte {offset}: {source}")
+            }
+            FlakeShowError::Io(err) => write!(f, "{err}"),
+            FlakeShowError::UnsupportedLockVersion(version) => {
+                write!(f, "unsupported flake.lock version {version}")
+            }
+            FlakeShowError::UnsupportedSchemaVersion(version) => {
+                write!(f, "unsupported flake info schema version {version}")
+            }
+            FlakeShowError::MissingRecording { args } => {
+                write!(f, "no recorded nix output for `nix {}`", args.join(" "))
+            }
+            FlakeShowError::InvalidFlakeRef { input, reason } => {
+                write!(f, "invalid flake reference `{input}`: {reason}")
+            }
+            FlakeShowError::Utf8(err) => write!(f, "nix output is not valid UTF-8: {err}"),
+            FlakeShowError::MetaCountMismatch { expected, got } => {
+                write!(f, "expected meta for {expected} derivations, got {got}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for FlakeShowError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FlakeShowError::Spawn(err) | FlakeShowError::Io(err) => Some(err),
+            FlakeShowError::Json { source, .. } => Some(source),
+            FlakeShowError::Utf8(err) => Some(err),
+            FlakeShowError::NixNotFound
+            | FlakeShowError::NonZeroExit { .. }
+            | FlakeShowError::UnsupportedLockVersion(_)
+            | FlakeShowError::UnsupportedSchemaVersion(_)
+            | FlakeShowError::MissingRecording { .. }
+            | FlakeShowError::InvalidFlakeRef { .. }
+            | FlakeShowError::MetaCountMismatch { .. }
+            | FlakeShowError::Timeout { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_error_offset() {
+        let input = b"{\n  \"packages\": [}\n";
+        let err = serde_json::from_slice::<serde_json::Value>(input).unwrap_err();
+        match FlakeShowError::json(err, input) {
+            FlakeShowError::Json { offset, .. } => assert_eq!(input[offset], b'}'),
+            other => panic!("unexpected error {other:?}"),
+        }
+    }
+}
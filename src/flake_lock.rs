@@ -0,0 +1,447 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{FlakeRef, FlakeRefKind, FlakeShowError};
+
+/// A parsed `flake.lock`: a graph of nodes, starting at [`FlakeLock::root`],
+/// whose edges are the inputs each flake declares.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FlakeLock {
+    pub version: u32,
+    /// key of the node for the flake itself.
+    pub root: String,
+    pub nodes: BTreeMap<String, LockNode>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LockNode {
+    /// from input name to where it points.
+    #[serde(default)]
+    pub inputs: BTreeMap<String, LockInput>,
+    /// the exact source that was locked; absent for the root node.
+    pub locked: Option<LockedRef>,
+    /// the reference as written in `flake.nix`.
+    pub original: Option<LockedRef>,
+    /// `false` for `flake = false` inputs.
+    #[serde(default = "default_flake", skip_serializing_if = "is_true")]
+    pub flake: bool,
+}
+
+fn default_flake() -> bool {
+    true
+}
+
+fn is_true(flake: &bool) -> bool {
+    *flake
+}
+
+/// Where an input points: straight at a node, or at another input by its
+/// path from the root, e.g. `inputs.foo.inputs.nixpkgs.follows = "nixpkgs"`
+/// becomes `Follows(["nixpkgs"])`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum LockInput {
+    Node(String),
+    Follows(Vec<String>),
+}
+
+/// The attributes of a `locked` or `original` entry.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LockedRef {
+    pub r#type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repo: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub r#ref: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rev: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dir: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nar_hash: Option<String>,
+    /// seconds since the epoch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rev_count: Option<u64>,
+    /// anything else, e.g. `submodules`.
+    #[serde(flatten)]
+    pub attrs: BTreeMap<String, serde_json::Value>,
+}
+
+impl LockedRef {
+    /// The flake reference these attributes describe.
+    pub fn to_flake_ref(&self) -> Result<FlakeRef, FlakeShowError> {
+        let missing = |reason| FlakeShowError::InvalidFlakeRef {
+            input: serde_json::to_string(self).unwrap_or_default(),
+            reason,
+        };
+        let url = || self.url.clone().ok_or_else(|| missing("missing `url`"));
+        let forge = || match (&self.owner, &self.repo) {
+            (Some(owner), Some(repo)) => Ok((owner.clone(), repo.clone(), self.host.clone())),
+            _ => Err(missing("missing `owner` or `repo`")),
+        };
+
+        let kind = match self.r#type.as_str() {
+            "path" => FlakeRefKind::Path {
+                path: self
+                    .path
+                    .clone()
+                    .ok_or_else(|| missing("missing `path`"))?
+                    .into(),
+            },
+            "git" => FlakeRefKind::Git { url: url()? },
+            "tarball" => FlakeRefKind::Tarball { url: url()? },
+            "file" => FlakeRefKind::File { url: url()? },
+            "indirect" => FlakeRefKind::Indirect {
+                id: self.id.clone().ok_or_else(|| missing("missing `id`"))?,
+            },
+            "github" => {
+                let (owner, repo, host) = forge()?;
+                FlakeRefKind::GitHub { owner, repo, host }
+            }
+            "gitlab" => {
+                let (owner, repo, host) = forge()?;
+                FlakeRefKind::GitLab { owner, repo, host }
+            }
+            "sourcehut" => {
+                let (owner, repo, host) = forge()?;
+                FlakeRefKind::SourceHut { owner, repo, host }
+            }
+            _ => return Err(missing("unsupported input type")),
+        };
+
+        let mut flake_ref = FlakeRef::new(kind);
+        flake_ref.r#ref = self.r#ref.clone();
+        flake_ref.rev = self.rev.clone();
+        flake_ref.dir = self.dir.clone();
+        flake_ref.nar_hash = self.nar_hash.clone();
+        if let Some(last_modified) = self.last_modified {
+            flake_ref
+                .attrs
+                .insert("lastModified".to_string(), last_modified.to_string());
+        }
+        if let Some(rev_count) = self.rev_count {
+            flake_ref
+                .attrs
+                .insert("revCount".to_string(), rev_count.to_string());
+        }
+        for (key, value) in &self.attrs {
+            let value = match value {
+                serde_json::Value::String(s) => s.clone(),
+                serde_json::Value::Bool(b) => u8::from(*b).to_string(),
+                other => other.to_string(),
+            };
+            flake_ref.attrs.insert(key.clone(), value);
+        }
+        Ok(flake_ref)
+    }
+}
+
+/// follows chains longer than this are treated as cycles.
+const MAX_FOLLOWS_DEPTH: usize = 64;
+
+impl FlakeLock {
+    pub fn parse(v: &[u8]) -> Result<Self, FlakeShowError> {
+        let lock: FlakeLock =
+            serde_json::from_slice(v).map_err(|err| FlakeShowError::json(err, v))?;
+        lock.check_version()
+    }
+
+    /// Reject lock files in a format this crate doesn't understand.
+    pub(crate) fn check_version(self) -> Result<Self, FlakeShowError> {
+        if !(5..=7).contains(&self.version) {
+            return Err(FlakeShowError::UnsupportedLockVersion(self.version));
+        }
+        Ok(self)
+    }
+
+    /// Read and parse a `flake.lock`, or the `flake.lock` inside a directory.
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, FlakeShowError> {
+        let mut path = path.as_ref().to_path_buf();
+        if path.is_dir() {
+            path.push("flake.lock");
+        }
+        Self::parse(&std::fs::read(path).map_err(FlakeShowError::Io)?)
+    }
+
+    pub fn node(&self, key: &str) -> Option<&LockNode> {
+        self.nodes.get(key)
+    }
+
+    pub fn root_node(&self) -> Option<&LockNode> {
+        self.node(&self.root)
+    }
+
+    /// The key of the node an input path such as `["home-manager",
+    /// "nixpkgs"]` (i.e. `home-manager/nixpkgs`) ends up at, following any
+    /// `follows` along the way.
+    pub fn resolve(&self, input_path: &[&str]) -> Option<&str> {
+        self.resolve_from_root(input_path.iter().copied(), 0)
+    }
+
+    /// The key of the node `input` points to.
+    pub fn resolve_input<'a>(&'a self, input: &'a LockInput) -> Option<&'a str> {
+        self.resolve_input_at_depth(input, 0)
+    }
+
+    fn resolve_input_at_depth<'a>(&'a self, input: &'a LockInput, depth: usize) -> Option<&'a str> {
+        match input {
+            LockInput::Node(key) => Some(key),
+            LockInput::Follows(path) => {
+                self.resolve_from_root(path.iter().map(String::as_str), depth + 1)
+            }
+        }
+    }
+
+    fn resolve_from_root<'a, 'p>(
+        &'a self,
+        input_path: impl Iterator<Item = &'p str>,
+        depth: usize,
+    ) -> Option<&'a str> {
+        if depth > MAX_FOLLOWS_DEPTH {
+            return None;
+        }
+        let mut key = self.root.as_str();
+        for name in input_path {
+            let input = self.node(key)?.inputs.get(name)?;
+            key = self.resolve_input_at_depth(input, depth)?;
+        }
+        Some(key)
+    }
+
+    /// The inputs of a node by name, with `follows` resolved to node keys.
+    pub fn resolved_inputs(&self, key: &str) -> BTreeMap<&str, &str> {
+        self.node(key)
+            .map(|node| {
+                node.inputs
+                    .iter()
+                    .filter_map(|(name, input)| Some((name.as_str(), self.resolve_input(input)?)))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Every input reachable from the root without going through a
+    /// `follows`, keyed by its input path such as `home-manager/systems`.
+    /// A node reachable along several paths is only listed under the
+    /// shortest one, the first in alphabetical order among equally short
+    /// ones.
+    pub fn input_paths(&self) -> BTreeMap<String, &LockNode> {
+        let mut paths = BTreeMap::new();
+        let mut seen = BTreeSet::from([self.root.as_str()]);
+        let mut todo = VecDeque::from([(String::new(), self.root.as_str())]);
+        while let Some((prefix, key)) = todo.pop_front() {
+            let Some(node) = self.node(key) else {
+                continue;
+            };
+            for (name, input) in &node.inputs {
+                let LockInput::Node(child) = input else {
+                    continue;
+                };
+                let Some(child_node) = self.node(child).filter(|_| seen.insert(child)) else {
+                    continue;
+                };
+                let path = match prefix.as_str() {
+                    "" => name.clone(),
+                    prefix => format!("{prefix}/{name}"),
+                };
+                paths.insert(path.clone(), child_node);
+                todo.push_back((path, child.as_str()));
+            }
+        }
+        paths
+    }
+
+    /// Keys of every node the root depends on, directly or transitively.
+    pub fn transitive_inputs(&self) -> BTreeSet<&str> {
+        let mut seen = BTreeSet::new();
+        let mut todo = vec![self.root.as_str()];
+        while let Some(key) = todo.pop() {
+            for (_, input) in self.resolved_inputs(key) {
+                if input != self.root && seen.insert(input) {
+                    todo.push(input);
+                }
+            }
+        }
+        seen
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCK: &str = r#"{
+      "nodes": {
+        "flake-utils": {
+          "inputs": { "systems": "systems" },
+          "locked": {
+            "lastModified": 1710146030,
+            "narHash": "sha256-SZ5L6eA7HJ/nmkzGG7/ISclqe6oZdOZTNoesiInkXPQ=",
+            "owner": "numtide",
+            "repo": "flake-utils",
+            "rev": "b1d9ab70662946ef0850d488da1c9019f3a9752a",
+            "type": "github"
+          },
+          "original": { "owner": "numtide", "repo": "flake-utils", "type": "github" }
+        },
+        "home-manager": {
+          "inputs": { "nixpkgs": ["nixpkgs"] },
+          "locked": {
+            "lastModified": 1711000000,
+            "narHash": "sha256-abc=",
+            "ref": "refs/heads/master",
+            "rev": "0123456789abcdef0123456789abcdef01234567",
+            "revCount": 42,
+            "type": "git",
+            "url": "https://github.com/nix-community/home-manager"
+          },
+          "original": { "type": "git", "url": "https://github.com/nix-community/home-manager" }
+        },
+        "nixpkgs": {
+          "locked": {
+            "lastModified": 1711163522,
+            "narHash": "sha256-YN/Ciidm+A0fmJPWlHBGvVkcarYWSC+s3NTPk/P+q3c=",
+            "owner": "NixOS",
+            "repo": "nixpkgs",
+            "rev": "44d0940ea560dee511026a53f0e2e2cde489b4d4",
+            "type": "github"
+          },
+          "original": { "id": "nixpkgs", "ref": "nixos-unstable", "type": "indirect" }
+        },
+        "root": {
+          "inputs": {
+            "flake-utils": "flake-utils",
+            "home-manager": "home-manager",
+            "nixpkgs": "nixpkgs",
+            "pkgs-alias": ["home-manager", "nixpkgs"]
+          }
+        },
+        "systems": {
+          "flake": false,
+          "locked": {
+            "lastModified": 1681028828,
+            "narHash": "sha256-Vy1rq5AaRuLzOxct8nz4T6wlgyUR7zLU309k9mBC768=",
+            "owner": "nix-systems",
+            "repo": "default",
+            "rev": "da67096a3b9bf56a91d16901293e51ba5b49a27e",
+            "type": "github"
+          },
+          "original": { "owner": "nix-systems", "repo": "default", "type": "github" }
+        }
+      },
+      "root": "root",
+      "version": 7
+    }"#;
+
+    #[test]
+    fn resolves_follows() {
+        let lock = FlakeLock::parse(LOCK.as_bytes()).unwrap();
+
+        assert_eq!(lock.resolve(&[]), Some("root"));
+        assert_eq!(lock.resolve(&["home-manager", "nixpkgs"]), Some("nixpkgs"));
+        assert_eq!(lock.resolve(&["pkgs-alias"]), Some("nixpkgs"));
+        assert_eq!(lock.resolve(&["flake-utils", "systems"]), Some("systems"));
+        assert_eq!(lock.resolve(&["missing"]), None);
+
+        assert_eq!(
+            lock.transitive_inputs().into_iter().collect::<Vec<_>>(),
+            ["flake-utils", "home-manager", "nixpkgs", "systems"]
+        );
+        assert!(!lock.node("systems").unwrap().flake);
+        assert_eq!(
+            lock.input_paths().into_keys().collect::<Vec<_>>(),
+            [
+                "flake-utils",
+                "flake-utils/systems",
+                "home-manager",
+                "nixpkgs"
+            ]
+        );
+    }
+
+    #[test]
+    fn locked_refs() {
+        let lock = FlakeLock::parse(LOCK.as_bytes()).unwrap();
+
+        let nixpkgs = lock.node("nixpkgs").unwrap();
+        let locked = nixpkgs.locked.as_ref().unwrap();
+        assert_eq!(locked.last_modified, Some(1711163522));
+        assert_eq!(
+            locked.to_flake_ref().unwrap().to_string(),
+            "github:NixOS/nixpkgs/44d0940ea560dee511026a53f0e2e2cde489b4d4\
+             ?lastModified=1711163522\
+             &narHash=sha256-YN/Ciidm%2BA0fmJPWlHBGvVkcarYWSC%2Bs3NTPk/P%2Bq3c%3D"
+        );
+        let original = nixpkgs.original.as_ref().unwrap().to_flake_ref().unwrap();
+        assert_eq!(original.to_string(), "nixpkgs/nixos-unstable");
+
+        let home_manager = lock.node("home-manager").unwrap().locked.as_ref().unwrap();
+        assert_eq!(home_manager.rev_count, Some(42));
+        assert_eq!(
+            home_manager.to_flake_ref().unwrap().kind,
+            FlakeRefKind::Git {
+                url: "https://github.com/nix-community/home-manager".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn cyclic_follows_do_not_hang() {
+        let lock = FlakeLock::parse(
+            br#"{"nodes": {"root": {"inputs": {"a": ["b"], "b": ["a"]}}}, "root": "root", "version": 7}"#,
+        )
+        .unwrap();
+        assert_eq!(lock.resolve(&["a"]), None);
+        assert!(lock.transitive_inputs().is_empty());
+    }
+
+    #[test]
+    fn diamonds_are_walked_once() {
+        // every `dN` reaches `dN+1` through both `a` and `b`, which is
+        // 2^40 paths to the last one
+        let mut nodes = vec![r#""root": {"inputs": {"start": "d0"}}"#.to_string()];
+        for n in 0..40 {
+            nodes.push(format!(
+                r#""d{n}": {{"inputs": {{"a": "a{n}", "b": "b{n}"}}}},
+                   "a{n}": {{"inputs": {{"next": "d{next}"}}}},
+                   "b{n}": {{"inputs": {{"next": "d{next}"}}}}"#,
+                next = n + 1
+            ));
+        }
+        nodes.push(r#""d40": {}"#.to_string());
+        let lock = FlakeLock::parse(
+            format!(
+                r#"{{"nodes": {{{}}}, "root": "root", "version": 7}}"#,
+                nodes.join(",")
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+
+        let paths = lock.input_paths();
+        assert_eq!(paths.len(), 121);
+        assert!(paths.contains_key("start/a/next/b"));
+        assert!(!paths.contains_key("start/b/next"));
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        let err = FlakeLock::parse(br#"{"nodes": {}, "root": "root", "version": 99}"#).unwrap_err();
+        assert!(matches!(err, FlakeShowError::UnsupportedLockVersion(99)));
+    }
+}
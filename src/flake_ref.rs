@@ -0,0 +1,575 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::FlakeShowError;
+
+/// A flake reference, such as `github:NixOS/nixpkgs/nixos-unstable`,
+/// `git+https://example.com/repo.git?ref=main` or `./some/path`.
+///
+/// Parses every form `nix` accepts and renders back to the URL syntax, so
+/// that `flake_ref.to_string().parse()` yields the same reference.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlakeRef {
+    pub kind: FlakeRefKind,
+    pub r#ref: Option<String>,
+    pub rev: Option<String>,
+    /// subdirectory of the source that contains `flake.nix`.
+    pub dir: Option<String>,
+    pub nar_hash: Option<String>,
+    /// any other attributes, e.g. `submodules` or `lastModified`.
+    pub attrs: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FlakeRefKind {
+    /// `path:/some/dir`, or a bare path like `./dir`.
+    Path { path: PathBuf },
+    /// `git+https://...`, `git+ssh://...`, `git+file://...`; `url` is the
+    /// transport URL without the `git+` prefix.
+    Git { url: String },
+    GitHub {
+        owner: String,
+        repo: String,
+        host: Option<String>,
+    },
+    GitLab {
+        owner: String,
+        repo: String,
+        host: Option<String>,
+    },
+    /// `sourcehut:~owner/repo`, the owner includes the `~`.
+    SourceHut {
+        owner: String,
+        repo: String,
+        host: Option<String>,
+    },
+    /// an archive to unpack, e.g. `https://example.com/src.tar.gz`.
+    Tarball { url: String },
+    /// a single file, e.g. `file+https://example.com/flake.nix`.
+    File { url: String },
+    /// a name looked up in the flake registry, e.g. `nixpkgs`.
+    Indirect { id: String },
+}
+
+const ARCHIVE_EXTENSIONS: &[&str] = &[
+    ".zip", ".tar", ".tgz", ".tar.gz", ".tar.xz", ".tar.bz2", ".tar.zst",
+];
+
+impl FlakeRef {
+    pub fn new(kind: FlakeRefKind) -> Self {
+        Self {
+            kind,
+            r#ref: None,
+            rev: None,
+            dir: None,
+            nar_hash: None,
+            attrs: BTreeMap::new(),
+        }
+    }
+
+    pub fn path(path: impl Into<PathBuf>) -> Self {
+        Self::new(FlakeRefKind::Path { path: path.into() })
+    }
+
+    pub fn github(owner: impl Into<String>, repo: impl Into<String>) -> Self {
+        Self::new(FlakeRefKind::GitHub {
+            owner: owner.into(),
+            repo: repo.into(),
+            host: None,
+        })
+    }
+
+    pub fn indirect(id: impl Into<String>) -> Self {
+        Self::new(FlakeRefKind::Indirect { id: id.into() })
+    }
+
+    pub fn with_ref(mut self, r#ref: impl Into<String>) -> Self {
+        self.r#ref = Some(r#ref.into());
+        self
+    }
+
+    pub fn with_rev(mut self, rev: impl Into<String>) -> Self {
+        self.rev = Some(rev.into());
+        self
+    }
+
+    pub fn with_dir(mut self, dir: impl Into<String>) -> Self {
+        self.dir = Some(dir.into());
+        self
+    }
+
+    fn set_attr(&mut self, key: &str, value: String) {
+        match key {
+            "ref" => self.r#ref = Some(value),
+            "rev" => self.rev = Some(value),
+            "dir" => self.dir = Some(value),
+            "narHash" => self.nar_hash = Some(value),
+            _ => {
+                self.attrs.insert(key.to_string(), value);
+            }
+        }
+    }
+}
+
+/// A relative path whose first component has a `:` in it, such as
+/// `github:owner/repo`, is parsed like nix parses it on the command line.
+/// Write `./github:owner/repo` for a directory of that name.
+impl From<PathBuf> for FlakeRef {
+    fn from(value: PathBuf) -> Self {
+        FlakeRef::from(value.as_path())
+    }
+}
+
+impl From<&Path> for FlakeRef {
+    fn from(value: &Path) -> Self {
+        value
+            .to_str()
+            .filter(|path| {
+                value.is_relative() && path.split('/').next().is_some_and(|c| c.contains(':'))
+            })
+            .and_then(|path| path.parse().ok())
+            .unwrap_or_else(|| FlakeRef::path(value))
+    }
+}
+
+fn invalid(input: &str, reason: &'static str) -> FlakeShowError {
+    FlakeShowError::InvalidFlakeRef {
+        input: input.to_string(),
+        reason,
+    }
+}
+
+fn is_rev(s: &str) -> bool {
+    (s.len() == 40 || s.len() == 64) && s.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+fn is_archive(url: &str) -> bool {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    ARCHIVE_EXTENSIONS.iter().any(|ext| path.ends_with(ext))
+}
+
+fn is_indirect_id(id: &str) -> bool {
+    id.starts_with(|c: char| c.is_ascii_alphabetic())
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+impl FromStr for FlakeRef {
+    type Err = FlakeShowError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        if input.contains('#') {
+            return Err(invalid(input, "a flake reference can't have a fragment"));
+        }
+
+        let (base, query) = match input.split_once('?') {
+            Some((base, query)) => (base, Some(query)),
+            None => (input, None),
+        };
+        if base.is_empty() {
+            return Err(invalid(input, "empty flake reference"));
+        }
+
+        let mut query_attrs = Vec::new();
+        for pair in query.into_iter().flat_map(|q| q.split('&')) {
+            if pair.is_empty() {
+                continue;
+            }
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            query_attrs.push((
+                percent_decode(key).ok_or_else(|| invalid(input, "bad percent encoding"))?,
+                percent_decode(value).ok_or_else(|| invalid(input, "bad percent encoding"))?,
+            ));
+        }
+
+        let mut flake_ref = if base.starts_with('/') || base.starts_with('.') {
+            FlakeRef::path(base)
+        } else if let Some((scheme, rest)) = base.split_once(':') {
+            match scheme {
+                "path" => FlakeRef::path(
+                    percent_decode(rest).ok_or_else(|| invalid(input, "bad percent encoding"))?,
+                ),
+                "github" | "gitlab" | "sourcehut" => {
+                    let host = query_attrs
+                        .iter()
+                        .position(|(key, _)| key == "host")
+                        .map(|i| query_attrs.remove(i).1);
+                    let mut segments = rest.split('/');
+                    let owner = segments.next().filter(|s| !s.is_empty());
+                    let repo = segments.next().filter(|s| !s.is_empty());
+                    let (Some(owner), Some(repo)) = (owner, repo) else {
+                        return Err(invalid(input, "expected `<owner>/<repo>`"));
+                    };
+                    let ref_or_rev = segments.next();
+                    if segments.next().is_some() {
+                        return Err(invalid(input, "too many path segments"));
+                    }
+
+                    let (owner, repo) = (owner.to_string(), repo.to_string());
+                    let mut flake_ref = FlakeRef::new(match scheme {
+                        "github" => FlakeRefKind::GitHub { owner, repo, host },
+                        "gitlab" => FlakeRefKind::GitLab { owner, repo, host },
+                        _ => FlakeRefKind::SourceHut { owner, repo, host },
+                    });
+                    match ref_or_rev {
+                        Some(rev) if is_rev(rev) => flake_ref.rev = Some(rev.to_string()),
+                        Some(r#ref) => flake_ref.r#ref = Some(r#ref.to_string()),
+                        None => {}
+                    }
+                    flake_ref
+                }
+                "flake" => parse_indirect(input, rest)?,
+                "http" | "https" | "file" if is_archive(base) => {
+                    FlakeRef::new(FlakeRefKind::Tarball {
+                        url: base.to_string(),
+                    })
+                }
+                "http" | "https" | "file" => FlakeRef::new(FlakeRefKind::File {
+                    url: base.to_string(),
+                }),
+                _ => {
+                    let url = |prefix: &str| base[prefix.len()..].to_string();
+                    if scheme.starts_with("git+") {
+                        FlakeRef::new(FlakeRefKind::Git { url: url("git+") })
+                    } else if scheme.starts_with("tarball+") {
+                        FlakeRef::new(FlakeRefKind::Tarball {
+                            url: url("tarball+"),
+                        })
+                    } else if scheme.starts_with("file+") {
+                        FlakeRef::new(FlakeRefKind::File { url: url("file+") })
+                    } else {
+                        return Err(invalid(input, "unsupported flake reference scheme"));
+                    }
+                }
+            }
+        } else {
+            parse_indirect(input, base)?
+        };
+
+        for (key, value) in query_attrs {
+            flake_ref.set_attr(&key, value);
+        }
+        Ok(flake_ref)
+    }
+}
+
+fn parse_indirect(input: &str, rest: &str) -> Result<FlakeRef, FlakeShowError> {
+    let mut segments = rest.split('/');
+    let id = segments.next().unwrap_or_default();
+    if !is_indirect_id(id) {
+        return Err(invalid(input, "not a valid flake registry id"));
+    }
+
+    let mut flake_ref = FlakeRef::indirect(id);
+    match (segments.next(), segments.next(), segments.next()) {
+        (None, ..) => {}
+        (Some(rev), None, _) if is_rev(rev) => flake_ref.rev = Some(rev.to_string()),
+        (Some(r#ref), None, _) => flake_ref.r#ref = Some(r#ref.to_string()),
+        (Some(r#ref), Some(rev), None) if is_rev(rev) => {
+            flake_ref.r#ref = Some(r#ref.to_string());
+            flake_ref.rev = Some(rev.to_string());
+        }
+        _ => return Err(invalid(input, "expected `<id>[/<ref>][/<rev>]`")),
+    }
+    Ok(flake_ref)
+}
+
+impl fmt::Display for FlakeRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // attributes that end up in the query string, in nix's (sorted) order.
+        let mut query: BTreeMap<&str, &str> = self
+            .attrs
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+        if let Some(dir) = &self.dir {
+            query.insert("dir", dir);
+        }
+        if let Some(nar_hash) = &self.nar_hash {
+            query.insert("narHash", nar_hash);
+        }
+
+        let (scheme, owner, repo, host) = match &self.kind {
+            FlakeRefKind::GitHub { owner, repo, host } => ("github", owner, repo, host),
+            FlakeRefKind::GitLab { owner, repo, host } => ("gitlab", owner, repo, host),
+            FlakeRefKind::SourceHut { owner, repo, host } => ("sourcehut", owner, repo, host),
+            FlakeRefKind::Indirect { id } => {
+                // both ref and rev live in the path for registry lookups,
+                // unless the ref wouldn't survive as a single segment.
+                write!(f, "{id}")?;
+                match &self.r#ref {
+                    Some(r#ref) if !is_segment(r#ref) => {
+                        query.insert("ref", r#ref);
+                    }
+                    Some(r#ref) => write!(f, "/{ref}")?,
+                    None => {}
+                }
+                if let Some(rev) = &self.rev {
+                    write!(f, "/{rev}")?;
+                }
+                return write_query(f, query);
+            }
+            kind => {
+                match kind {
+                    FlakeRefKind::Path { path } => {
+                        let path = path.display().to_string();
+                        let plain = query.is_empty() && self.r#ref.is_none() && self.rev.is_none();
+                        if path.contains(['?', '#']) {
+                            // only the `path:` form is percent decoded.
+                            write!(f, "path:{}", escape_path(&path))?;
+                        } else if path.starts_with('/') && !plain {
+                            write!(f, "path:{path}")?;
+                        } else if path.starts_with(['/', '.']) {
+                            write!(f, "{path}")?;
+                        } else {
+                            // `path:sub` would copy `sub` to the store
+                            // rather than fetch it with git like nix does
+                            // for `./sub`.
+                            write!(f, "./{path}")?;
+                        }
+                    }
+                    FlakeRefKind::Git { url } => write!(f, "git+{url}")?,
+                    FlakeRefKind::Tarball { url } if is_archive(url) && is_plain_url(url) => {
+                        write!(f, "{url}")?
+                    }
+                    FlakeRefKind::Tarball { url } => write!(f, "tarball+{url}")?,
+                    FlakeRefKind::File { url } if !is_archive(url) && is_plain_url(url) => {
+                        write!(f, "{url}")?
+                    }
+                    FlakeRefKind::File { url } => write!(f, "file+{url}")?,
+                    _ => unreachable!("forge and indirect references are rendered above"),
+                }
+                if let Some(r#ref) = &self.r#ref {
+                    query.insert("ref", r#ref);
+                }
+                if let Some(rev) = &self.rev {
+                    query.insert("rev", rev);
+                }
+                return write_query(f, query);
+            }
+        };
+
+        // forges take either the rev or the ref as a third path segment.
+        write!(f, "{scheme}:{owner}/{repo}")?;
+        match (&self.r#ref, &self.rev) {
+            (Some(r#ref), Some(rev)) => {
+                write!(f, "/{rev}")?;
+                query.insert("ref", r#ref);
+            }
+            (Some(r#ref), None) if !is_segment(r#ref) => {
+                query.insert("ref", r#ref);
+            }
+            (Some(segment), None) | (None, Some(segment)) => write!(f, "/{segment}")?,
+            (None, None) => {}
+        }
+        if let Some(host) = host {
+            query.insert("host", host);
+        }
+        write_query(f, query)
+    }
+}
+
+fn write_query(f: &mut fmt::Formatter<'_>, query: BTreeMap<&str, &str>) -> fmt::Result {
+    for (i, (key, value)) in query.into_iter().enumerate() {
+        let sep = if i == 0 { '?' } else { '&' };
+        write!(f, "{sep}{}={}", percent_encode(key), percent_encode(value))?;
+    }
+    Ok(())
+}
+
+/// Whether `s` can be written as a path segment as is, e.g. a branch name
+/// without a `/`.
+fn is_segment(s: &str) -> bool {
+    !s.is_empty() && percent_encode(s) == s && !s.contains('/')
+}
+
+/// `path` with the characters that would end it escaped.
+fn escape_path(path: &str) -> String {
+    path.replace('%', "%25")
+        .replace('?', "%3F")
+        .replace('#', "%23")
+}
+
+/// urls nix treats as a tarball or file without a `tarball+`/`file+` prefix.
+fn is_plain_url(url: &str) -> bool {
+    ["http://", "https://", "file:"]
+        .iter()
+        .any(|scheme| url.starts_with(scheme))
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                out.push(b as char)
+            }
+            b':' | b'@' | b'/' => out.push(b as char),
+            _ => out.push_str(&format!("%{b:02X}")),
+        }
+    }
+    out
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s.get(i + 1..i + 3)?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REV: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    fn parse(s: &str) -> FlakeRef {
+        s.parse().unwrap_or_else(|err| panic!("{s}: {err}"))
+    }
+
+    #[test]
+    fn round_trips() {
+        for input in [
+            "/home/me/flake",
+            "./flake",
+            ".",
+            "path:/home/me/flake?narHash=sha256-abc%2B/%3D",
+            "git+https://example.com/repo.git?dir=sub&ref=main",
+            "git+ssh://git@github.com/NixOS/nix?ref=master",
+            "git+file:///home/me/repo?submodules=1",
+            "github:NixOS/nixpkgs",
+            "github:NixOS/nixpkgs/nixos-23.11",
+            &format!("github:NixOS/nixpkgs/{REV}?dir=lib"),
+            "github:corp/repo?host=github.corp.com",
+            "gitlab:veloren/veloren/master",
+            "sourcehut:~misterio/nix-colors",
+            "https://github.com/NixOS/patchelf/archive/master.tar.gz",
+            "tarball+https://example.com/download",
+            "file+https://example.com/flake.tar.gz",
+            "https://example.com/flake.nix",
+            "nixpkgs",
+            "nixpkgs/nixos-23.11",
+            &format!("nixpkgs/nixos-23.11/{REV}"),
+            "github:a/b?ref=feature/x",
+            &format!("github:a/b/{REV}?ref=feature/x"),
+            "nixpkgs?ref=feature/x",
+            "./sub?dir=nested",
+            "path:/what%3F/%23hash/100%25",
+        ] {
+            let flake_ref = parse(input);
+            assert_eq!(flake_ref.to_string(), input);
+            assert_eq!(parse(&flake_ref.to_string()), flake_ref);
+        }
+    }
+
+    #[test]
+    fn renders_built_references() {
+        for (flake_ref, rendered) in [
+            (
+                FlakeRef::github("a", "b").with_ref("feature/x"),
+                "github:a/b?ref=feature/x",
+            ),
+            (
+                FlakeRef::indirect("nixpkgs").with_ref("feature/x"),
+                "nixpkgs?ref=feature/x",
+            ),
+            (FlakeRef::path("sub"), "./sub"),
+            (
+                FlakeRef::path("sub/dir").with_dir("nested"),
+                "./sub/dir?dir=nested",
+            ),
+            (FlakeRef::path("/a?b#c"), "path:/a%3Fb%23c"),
+            (FlakeRef::path("a#b"), "path:a%23b"),
+        ] {
+            assert_eq!(flake_ref.to_string(), rendered);
+            let parsed = parse(rendered);
+            assert_eq!(parsed.to_string(), rendered);
+            assert_eq!(parsed.r#ref, flake_ref.r#ref);
+            assert_eq!(parsed.dir, flake_ref.dir);
+        }
+        assert_eq!(parse("path:/a%3Fb%23c"), FlakeRef::path("/a?b#c"));
+    }
+
+    #[test]
+    fn url_like_paths() {
+        assert_eq!(
+            FlakeRef::from(PathBuf::from("github:owner/repo")),
+            FlakeRef::github("owner", "repo")
+        );
+        assert_eq!(
+            FlakeRef::from(Path::new("./github:owner/repo")),
+            FlakeRef::path("./github:owner/repo")
+        );
+        assert_eq!(
+            FlakeRef::from(Path::new("sub/a:b")),
+            FlakeRef::path("sub/a:b")
+        );
+        assert_eq!(FlakeRef::from(Path::new("/a:b")), FlakeRef::path("/a:b"));
+    }
+
+    #[test]
+    fn parses_attributes() {
+        let github = parse("github:NixOS/templates?ref=main&dir=rust");
+        assert_eq!(
+            github.kind,
+            FlakeRefKind::GitHub {
+                owner: "NixOS".into(),
+                repo: "templates".into(),
+                host: None
+            }
+        );
+        assert_eq!(github.r#ref.as_deref(), Some("main"));
+        assert_eq!(github.dir.as_deref(), Some("rust"));
+        assert_eq!(github.to_string(), "github:NixOS/templates/main?dir=rust");
+
+        let rev = parse(&format!("github:NixOS/nixpkgs/{REV}"));
+        assert_eq!(rev.rev.as_deref(), Some(REV));
+        assert_eq!(rev.r#ref, None);
+
+        let indirect = parse("flake:nixpkgs/nixos-unstable");
+        assert_eq!(
+            indirect,
+            FlakeRef::indirect("nixpkgs").with_ref("nixos-unstable")
+        );
+
+        let path = parse("path:/some/where?narHash=sha256-abc%3D&lastModified=12");
+        assert_eq!(path.nar_hash.as_deref(), Some("sha256-abc="));
+        assert_eq!(path.attrs["lastModified"], "12");
+
+        assert_eq!(
+            parse(&format!("git+https://example.com/r.git?rev={REV}"))
+                .rev
+                .as_deref(),
+            Some(REV)
+        );
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        for input in [
+            "",
+            "github:NixOS",
+            "github:a/b/c/d",
+            "svn+https://example.com",
+            "nixpkgs#hello",
+            "1nixpkgs",
+            "path:/x?dir=%zz",
+        ] {
+            assert!(input.parse::<FlakeRef>().is_err(), "{input}");
+        }
+    }
+}
@@ -0,0 +1,335 @@
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+
+use crate::common::{common_setters, CommonArgs};
+use crate::log::strip_ansi;
+use crate::{FlakeRef, FlakeShowError, Installable, NixLogEvent, Verbosity};
+
+/// What instantiating a template did, see
+/// [`NixFlakeInitBuilder::into_instance`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TemplateInstance {
+    /// the files nix wrote, in order. Files that already existed with the
+    /// same contents are left alone and not listed.
+    pub created: Vec<PathBuf>,
+    /// files that already existed with different contents. nix refuses to
+    /// overwrite them, and fails after writing everything else.
+    pub conflicts: Vec<PathBuf>,
+    /// the template's `welcomeText`, as markdown.
+    pub welcome_text: Option<String>,
+    /// why `welcome_text` couldn't be looked up. The template has been
+    /// instantiated regardless.
+    pub welcome_text_error: Option<String>,
+}
+
+impl TemplateInstance {
+    /// Pick the written and conflicting files out of nix's log messages.
+    fn from_events(events: &[NixLogEvent]) -> Self {
+        let mut instance = TemplateInstance::default();
+        for event in events {
+            let NixLogEvent::Msg { level, msg } = event else {
+                continue;
+            };
+            let msg = strip_ansi(msg);
+            match level {
+                Verbosity::Error => {
+                    if let Some(path) = msg
+                        .strip_prefix("refusing to overwrite existing file '")
+                        .and_then(|rest| rest.split_once('\''))
+                    {
+                        instance.conflicts.push(path.0.into());
+                    }
+                }
+                _ => {
+                    if let Some(path) = msg.strip_prefix("wrote: ") {
+                        instance.created.push(path.trim_end().into());
+                    }
+                }
+            }
+        }
+        instance
+    }
+
+    pub fn has_conflicts(&self) -> bool {
+        !self.conflicts.is_empty()
+    }
+
+    /// `welcome_text` is `null` for templates without one. A failed lookup
+    /// is recorded rather than returned, it shouldn't hide files that were
+    /// already written.
+    fn set_welcome_text(&mut self, welcome_text: Result<Vec<u8>, FlakeShowError>) {
+        let welcome_text = welcome_text.and_then(|json| {
+            serde_json::from_slice(&json).map_err(|err| FlakeShowError::json(err, &json))
+        });
+        match welcome_text {
+            Ok(welcome_text) => self.welcome_text = welcome_text,
+            Err(err) => self.welcome_text_error = Some(err.to_string()),
+        }
+    }
+}
+
+/// nix exits with an error when a file conflicts, but the files reported
+/// up to that point have been written nonetheless.
+fn check_instantiated(
+    result: Result<Vec<u8>, FlakeShowError>,
+    instance: &TemplateInstance,
+) -> Result<(), FlakeShowError> {
+    match result {
+        Ok(_) => Ok(()),
+        Err(FlakeShowError::NonZeroExit { .. }) if instance.has_conflicts() => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+/// `nix flake init`, which copies a template into an existing directory,
+/// or `nix flake new`, which creates a directory for it.
+#[derive(Default, Debug)]
+pub struct NixFlakeInitBuilder {
+    template: Option<(FlakeRef, String)>,
+    /// `Some` for `nix flake new`.
+    new_dir: Option<PathBuf>,
+    dir: Option<PathBuf>,
+    common: CommonArgs,
+}
+
+impl NixFlakeInitBuilder {
+    common_setters!();
+
+    pub(crate) fn new_flake(dir: PathBuf) -> Self {
+        NixFlakeInitBuilder {
+            new_dir: Some(dir),
+            ..NixFlakeInitBuilder::default()
+        }
+    }
+
+    /// The template `name` of `flake`, e.g. one listed in
+    /// [`FlakeInfo::templates`](crate::FlakeInfo::templates). nix uses
+    /// `templates#default` if none is given.
+    pub fn template(mut self, flake: impl Into<FlakeRef>, name: impl Into<String>) -> Self {
+        self.template = Some((flake.into(), name.into()));
+        self
+    }
+
+    /// The directory to run nix in, the current one by default. For
+    /// [`crate::flake_new`], a relative target directory is resolved
+    /// against it.
+    pub fn dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.dir = Some(dir.into());
+        self
+    }
+
+    /// Instantiate the template. Files that already exist with different
+    /// contents end up in [`TemplateInstance::conflicts`] rather than
+    /// failing the whole call.
+    pub fn into_instance(mut self) -> Result<TemplateInstance, FlakeShowError> {
+        let (events, welcome_cmd) = self.prepare();
+        let runner = self.common.take_runner();
+        let welcome_runner = runner.sibling();
+
+        let result = runner.run(&self.build());
+        let mut instance = TemplateInstance::from_events(&events.lock().unwrap());
+        check_instantiated(result, &instance)?;
+        if let Some(welcome_cmd) = welcome_cmd {
+            instance.set_welcome_text(welcome_runner.run(&welcome_cmd));
+        }
+        Ok(instance)
+    }
+
+    /// Collect log events for the list of files, and prepare the command
+    /// that looks up the welcome text.
+    fn prepare(&mut self) -> (Arc<Mutex<Vec<NixLogEvent>>>, Option<Command>) {
+        let events = self.common.collect_log_events();
+
+        let welcome_cmd = self.template.as_ref().map(|(flake, name)| {
+            let mut cmd = self.common.command(&["eval", "--json"]);
+            cmd.arg(Installable::flake_attr(flake.clone(), &["templates", name]).as_str())
+                .arg("--apply")
+                .arg("template: template.welcomeText or null");
+            // relative template flakes resolve against the same directory
+            // as the template itself
+            if let Some(dir) = &self.dir {
+                cmd.current_dir(dir);
+            }
+            self.common.push_flags(&mut cmd);
+            cmd
+        });
+
+        (events, welcome_cmd)
+    }
+
+    pub fn build(self) -> Command {
+        let mut cmd = match &self.new_dir {
+            Some(new_dir) => {
+                let mut cmd = self.common.command(&["flake", "new"]);
+                cmd.arg(new_dir);
+                cmd
+            }
+            None => self.common.command(&["flake", "init"]),
+        };
+
+        if let Some(dir) = &self.dir {
+            cmd.current_dir(dir);
+        }
+
+        if let Some((flake, name)) = &self.template {
+            cmd.arg("--template").arg(format!("{flake}#{name}"));
+        }
+
+        self.common.push_flags(&mut cmd);
+        cmd
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{flake_init, flake_new, NixOutput, ReplayBackend};
+
+    const INIT_LOG: &str = r#"@nix {"action":"msg","level":2,"msg":"wrote: \u001b[1m/src/app/Cargo.toml\u001b[0m"}
+@nix {"action":"msg","level":2,"msg":"wrote: /src/app/src/main.rs"}
+@nix {"action":"msg","level":0,"msg":"refusing to overwrite existing file '/src/app/flake.nix'\n please merge it manually with '/nix/store/t1-source/rust/flake.nix'"}
+@nix {"action":"msg","level":0,"msg":"\u001b[31;1merror:\u001b[0m encountered 1 conflicts - see above"}
+"#;
+
+    fn welcome_args(name: &str) -> [String; 5] {
+        [
+            "eval".into(),
+            "--json".into(),
+            format!("templates#templates.{name}"),
+            "--apply".into(),
+            "template: template.welcomeText or null".into(),
+        ]
+    }
+
+    #[test]
+    fn init_reports_conflicts() {
+        let backend = ReplayBackend::new()
+            .record(
+                [
+                    "flake",
+                    "init",
+                    "--template",
+                    "templates#rust",
+                    "--log-format",
+                    "internal-json",
+                ],
+                NixOutput::failure(1, INIT_LOG),
+            )
+            .record(
+                welcome_args("rust"),
+                NixOutput::success(r##""# Rust\n\nRun `cargo build`.""##),
+            );
+
+        let instance = flake_init()
+            .template(FlakeRef::indirect("templates"), "rust")
+            .dir("/src/app")
+            .backend(backend)
+            .into_instance()
+            .unwrap();
+
+        assert_eq!(
+            instance.created,
+            [
+                PathBuf::from("/src/app/Cargo.toml"),
+                PathBuf::from("/src/app/src/main.rs")
+            ]
+        );
+        assert_eq!(instance.conflicts, [PathBuf::from("/src/app/flake.nix")]);
+        assert_eq!(
+            instance.welcome_text.as_deref(),
+            Some("# Rust\n\nRun `cargo build`.")
+        );
+    }
+
+    #[test]
+    fn new_without_welcome_text() {
+        let backend = ReplayBackend::new()
+            .record(
+                [
+                    "flake",
+                    "new",
+                    "hello",
+                    "--template",
+                    "templates#c-hello",
+                    "--log-format",
+                    "internal-json",
+                ],
+                NixOutput::success("").with_stderr(
+                    r#"@nix {"action":"msg","level":2,"msg":"wrote: /src/hello/flake.nix"}"#,
+                ),
+            )
+            .record(welcome_args("c-hello"), NixOutput::success("null"));
+
+        let instance = flake_new("hello")
+            .template(FlakeRef::indirect("templates"), "c-hello")
+            .backend(backend)
+            .into_instance()
+            .unwrap();
+
+        assert_eq!(instance.created, [PathBuf::from("/src/hello/flake.nix")]);
+        assert!(!instance.has_conflicts());
+        assert_eq!(instance.welcome_text, None);
+        assert_eq!(instance.welcome_text_error, None);
+    }
+
+    #[test]
+    fn welcome_text_is_looked_up_in_dir() {
+        let mut builder = flake_init()
+            .template(FlakeRef::from(PathBuf::from("./templates")), "rust")
+            .dir("/src/app");
+        let (_, welcome_cmd) = builder.prepare();
+
+        assert_eq!(
+            welcome_cmd.unwrap().get_current_dir(),
+            Some(std::path::Path::new("/src/app"))
+        );
+    }
+
+    #[test]
+    fn welcome_text_failures_are_recorded() {
+        let backend = ReplayBackend::new()
+            .record(
+                [
+                    "flake",
+                    "init",
+                    "--template",
+                    "templates#rust",
+                    "--log-format",
+                    "internal-json",
+                ],
+                NixOutput::success("").with_stderr(
+                    r#"@nix {"action":"msg","level":2,"msg":"wrote: /src/app/flake.nix"}"#,
+                ),
+            )
+            .record(
+                welcome_args("rust"),
+                NixOutput::failure(1, "error: attribute 'rust' missing"),
+            );
+
+        let instance = flake_init()
+            .template(FlakeRef::indirect("templates"), "rust")
+            .backend(backend)
+            .into_instance()
+            .unwrap();
+
+        assert_eq!(instance.created, [PathBuf::from("/src/app/flake.nix")]);
+        assert_eq!(instance.welcome_text, None);
+        assert!(instance
+            .welcome_text_error
+            .is_some_and(|err| err.contains("attribute 'rust' missing")));
+    }
+
+    #[test]
+    fn other_failures_are_errors() {
+        let backend = ReplayBackend::new().record(
+            ["flake", "init", "--log-format", "internal-json"],
+            NixOutput::failure(1, "error: cannot find flake 'flake:templates'"),
+        );
+
+        assert!(matches!(
+            flake_init().backend(backend).into_instance(),
+            Err(FlakeShowError::NonZeroExit { .. })
+        ));
+    }
+}
@@ -0,0 +1,817 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{current_nix_system_with, DerivationMeta, FlakeShowError, NixBinary};
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FlakeShowOutput {
+    // from architecture to named fields
+    #[serde(default)]
+    apps: BTreeMap<String, AnatomyNode>,
+    #[serde(default)]
+    checks: BTreeMap<String, AnatomyNode>,
+    #[serde(default)]
+    dev_shells: BTreeMap<String, AnatomyNode>,
+    // from architecture straight to the derivation
+    #[serde(default)]
+    formatter: BTreeMap<String, AnatomyNode>,
+    #[serde(default)]
+    legacy_packages: BTreeMap<String, AnatomyNode>,
+    #[serde(default)]
+    packages: BTreeMap<String, AnatomyNode>,
+    // arbitrarily nested, conventionally job name then architecture
+    #[serde(default)]
+    hydra_jobs: Option<AnatomyNode>,
+    // from name to the kind of output
+    #[serde(default)]
+    overlays: BTreeMap<String, TypedLeaf>,
+    #[serde(default)]
+    nixos_modules: BTreeMap<String, TypedLeaf>,
+    #[serde(default)]
+    nixos_configurations: BTreeMap<String, TypedLeaf>,
+    #[serde(default)]
+    darwin_configurations: BTreeMap<String, TypedLeaf>,
+    #[serde(default)]
+    home_configurations: BTreeMap<String, TypedLeaf>,
+    #[serde(default)]
+    lib: Option<AnatomyNode>,
+    #[serde(default)]
+    templates: BTreeMap<String, TemplateDescription>,
+    // every top level output we don't know about
+    #[serde(flatten)]
+    other: BTreeMap<String, AnatomyNode>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TemplateDescription {
+    description: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TypedLeaf {
+    pub r#type: String,
+}
+
+/// The shape nix uses for every node it prints: either an object with a
+/// `type`, or an attribute set of further nodes.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum AnatomyNode {
+    Leaf {
+        r#type: String,
+        name: Option<String>,
+        description: Option<String>,
+    },
+    Attrs(BTreeMap<String, AnatomyNode>),
+}
+
+/// A generic node of a flake's output tree, as reported by
+/// `nix flake show --json`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "schema",
+    derive(Serialize, Deserialize),
+    serde(from = "TaggedNode", into = "TaggedNode")
+)]
+pub enum OutputNode {
+    /// Something nix recognised, such as a derivation, app or module.
+    Leaf {
+        kind: String,
+        name: Option<String>,
+        description: Option<String>,
+    },
+    /// An attribute set nix recursed into.
+    Attrs(BTreeMap<String, OutputNode>),
+    /// A value nix didn't inspect, such as `lib`.
+    Unknown,
+    /// A value nix skipped, e.g. another system without `--all-systems`.
+    Omitted,
+}
+
+impl From<AnatomyNode> for OutputNode {
+    fn from(value: AnatomyNode) -> Self {
+        match value {
+            AnatomyNode::Leaf { r#type, .. } if r#type == "unknown" => OutputNode::Unknown,
+            AnatomyNode::Leaf { r#type, .. } if r#type == "omitted" => OutputNode::Omitted,
+            AnatomyNode::Leaf {
+                r#type,
+                name,
+                description,
+            } => OutputNode::Leaf {
+                kind: r#type,
+                name,
+                description,
+            },
+            AnatomyNode::Attrs(children) => OutputNode::Attrs(nodes(children)),
+        }
+    }
+}
+
+fn nodes(anatomy: BTreeMap<String, AnatomyNode>) -> BTreeMap<String, OutputNode> {
+    anatomy
+        .into_iter()
+        .map(|(attr, node)| (attr, node.into()))
+        .collect()
+}
+
+/// A node of a flake's output tree, tagged with `node`: a `leaf` nix
+/// recognised, `attrs` with further `children`, or a value nix didn't
+/// inspect (`unknown`) or skipped (`omitted`).
+#[cfg(feature = "schema")]
+#[derive(Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(tag = "node", rename_all = "camelCase")]
+enum TaggedNode {
+    Leaf {
+        kind: String,
+        name: Option<String>,
+        description: Option<String>,
+    },
+    Attrs {
+        children: BTreeMap<String, OutputNode>,
+    },
+    Unknown,
+    Omitted,
+}
+
+#[cfg(feature = "schema")]
+impl From<TaggedNode> for OutputNode {
+    fn from(value: TaggedNode) -> Self {
+        match value {
+            TaggedNode::Leaf {
+                kind,
+                name,
+                description,
+            } => OutputNode::Leaf {
+                kind,
+                name,
+                description,
+            },
+            TaggedNode::Attrs { children } => OutputNode::Attrs(children),
+            TaggedNode::Unknown => OutputNode::Unknown,
+            TaggedNode::Omitted => OutputNode::Omitted,
+        }
+    }
+}
+
+#[cfg(feature = "schema")]
+impl From<OutputNode> for TaggedNode {
+    fn from(value: OutputNode) -> Self {
+        match value {
+            OutputNode::Leaf {
+                kind,
+                name,
+                description,
+            } => TaggedNode::Leaf {
+                kind,
+                name,
+                description,
+            },
+            OutputNode::Attrs(children) => TaggedNode::Attrs { children },
+            OutputNode::Unknown => TaggedNode::Unknown,
+            OutputNode::Omitted => TaggedNode::Omitted,
+        }
+    }
+}
+
+/// Described in the JSON schema by the shape it serializes to.
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for OutputNode {
+    fn schema_name() -> std::borrow::Cow<'static, str> {
+        "OutputNode".into()
+    }
+
+    fn json_schema(generator: &mut schemars::SchemaGenerator) -> schemars::Schema {
+        TaggedNode::json_schema(generator)
+    }
+}
+
+impl OutputNode {
+    /// The node at `path` below this one, if there is one.
+    pub fn get(&self, path: &[&str]) -> Option<&OutputNode> {
+        match path.split_first() {
+            None => Some(self),
+            Some((attr, rest)) => match self {
+                OutputNode::Attrs(children) => children.get(*attr)?.get(rest),
+                _ => None,
+            },
+        }
+    }
+
+    pub fn children(&self) -> Option<&BTreeMap<String, OutputNode>> {
+        match self {
+            OutputNode::Attrs(children) => Some(children),
+            _ => None,
+        }
+    }
+
+    /// Every node below this one that isn't an attribute set, along with
+    /// its attribute path relative to this node.
+    pub fn leaves(&self) -> Vec<(Vec<String>, &OutputNode)> {
+        fn walk<'a>(
+            node: &'a OutputNode,
+            path: &mut Vec<String>,
+            out: &mut Vec<(Vec<String>, &'a OutputNode)>,
+        ) {
+            match node {
+                OutputNode::Attrs(children) => {
+                    for (attr, child) in children {
+                        path.push(attr.clone());
+                        walk(child, path, out);
+                        path.pop();
+                    }
+                }
+                leaf => out.push((path.clone(), leaf)),
+            }
+        }
+
+        let mut out = Vec::new();
+        walk(self, &mut Vec::new(), &mut out);
+        out
+    }
+
+    /// every derivation below this node, along with its attribute path
+    /// relative to it. `prefix` is the path of this node within the flake.
+    fn derivations(&self, prefix: &[&str]) -> Vec<(Vec<String>, Derivation)> {
+        self.leaves()
+            .into_iter()
+            .filter_map(|(path, leaf)| match leaf {
+                OutputNode::Leaf {
+                    kind,
+                    name: Some(name),
+                    description,
+                } => {
+                    let deriv = Derivation {
+                        name: name.clone(),
+                        kind: kind.clone(),
+                        description: description.clone(),
+                        invocation: path.join("."),
+                        attr_path: prefix
+                            .iter()
+                            .map(|attr| attr.to_string())
+                            .chain(path.iter().cloned())
+                            .collect(),
+                        meta: None,
+                    };
+                    Some((path, deriv))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "schema",
+    derive(Serialize, Deserialize, schemars::JsonSchema),
+    serde(rename_all = "camelCase")
+)]
+pub struct Derivation {
+    pub name: String,
+    pub kind: String,
+    pub description: Option<String>,
+    pub invocation: String,
+    /// where this derivation lives in the flake's outputs, e.g.
+    /// `["packages", "x86_64-linux", "hello"]`.
+    pub attr_path: Vec<String>,
+    /// version, license and the like, `None` unless looked up with
+    /// [`crate::derivation_meta`].
+    #[cfg_attr(
+        feature = "schema",
+        schemars(description = "version, license and the like, null unless looked up separately.")
+    )]
+    pub meta: Option<DerivationMeta>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "schema",
+    derive(Serialize, Deserialize, schemars::JsonSchema),
+    serde(rename_all = "camelCase")
+)]
+pub struct App {
+    pub invocation: String,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "schema",
+    derive(Serialize, Deserialize, schemars::JsonSchema),
+    serde(rename_all = "camelCase")
+)]
+pub struct Overlay {
+    pub name: String,
+    /// the type nix reports, usually `nixpkgs-overlay`.
+    pub kind: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "schema",
+    derive(Serialize, Deserialize, schemars::JsonSchema),
+    serde(rename_all = "camelCase")
+)]
+pub struct NixosModule {
+    pub name: String,
+    /// the type nix reports, usually `nixos-module`.
+    pub kind: String,
+}
+
+/// An entry of `nixosConfigurations`, `darwinConfigurations` or
+/// `homeConfigurations`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "schema",
+    derive(Serialize, Deserialize, schemars::JsonSchema),
+    serde(rename_all = "camelCase")
+)]
+pub struct Configuration {
+    pub name: String,
+    /// the type nix reports, e.g. `nixos-configuration`, or `unknown`
+    /// for outputs nix doesn't inspect.
+    pub kind: String,
+}
+
+/// A derivation somewhere inside `hydraJobs`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "schema",
+    derive(Serialize, Deserialize, schemars::JsonSchema),
+    serde(rename_all = "camelCase")
+)]
+pub struct HydraJob {
+    /// attribute path below `hydraJobs`, e.g. `["tests", "x86_64-linux"]`.
+    pub path: Vec<String>,
+    pub derivation: Derivation,
+}
+
+impl HydraJob {
+    /// By convention jobs are laid out as `hydraJobs.<job>.<system>`, so
+    /// the system is the last attribute of a nested path.
+    pub fn system(&self) -> Option<&str> {
+        match self.path.as_slice() {
+            [_, .., system] => Some(system),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "schema",
+    derive(Serialize, Deserialize, schemars::JsonSchema),
+    serde(rename_all = "camelCase")
+)]
+pub struct IndividualFlakeInfos {
+    pub apps: Vec<App>,
+    pub checks: Vec<Derivation>,
+    pub dev_shells: Vec<Derivation>,
+    pub formatter: Option<Derivation>,
+    pub legacy_packages: Vec<Derivation>,
+    pub packages: Vec<Derivation>,
+    pub hydra_jobs: Vec<HydraJob>,
+}
+
+/// The outputs of a flake, as shown by `nix flake show`.
+///
+/// Systems, and the entries of each, are in attribute order, the order
+/// nix prints them in, so two runs over the same flake give equal infos.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "schema",
+    derive(Serialize, Deserialize, schemars::JsonSchema),
+    serde(rename_all = "camelCase")
+)]
+pub struct FlakeInfo {
+    /// from architecture to app
+    pub apps: BTreeMap<String, Vec<App>>,
+    /// from architecture to derivation
+    pub checks: BTreeMap<String, Vec<Derivation>>,
+    pub dev_shells: BTreeMap<String, Vec<Derivation>>,
+    pub packages: BTreeMap<String, Vec<Derivation>>,
+    /// from architecture to derivation, with nested package sets flattened
+    /// into a dotted invocation. Only populated when run with `--legacy`.
+    pub legacy_packages: BTreeMap<String, Vec<Derivation>>,
+    /// from architecture to the formatter used by `nix fmt`.
+    pub formatter: BTreeMap<String, Derivation>,
+    pub hydra_jobs: Vec<HydraJob>,
+    pub overlays: Vec<Overlay>,
+    pub nixos_modules: Vec<NixosModule>,
+    pub nixos_configurations: Vec<Configuration>,
+    pub darwin_configurations: Vec<Configuration>,
+    pub home_configurations: Vec<Configuration>,
+    /// the flake's `lib` output, which nix doesn't inspect, so it's
+    /// usually [`OutputNode::Unknown`].
+    #[cfg_attr(
+        feature = "schema",
+        schemars(
+            description = "the flake's `lib` output, which nix doesn't inspect, so it's usually an `unknown` node."
+        )
+    )]
+    pub lib: Option<OutputNode>,
+    /// from template name to template description.
+    pub templates: BTreeMap<String, String>,
+    /// every top level output not covered above, e.g. `deploy` or
+    /// `herculesCI`, from output name to its tree. Also holds whatever
+    /// the outputs above contain that isn't one of their entries, such
+    /// as `legacyPackages.x86_64-linux.lib`, under the output's name.
+    pub other_outputs: BTreeMap<String, OutputNode>,
+    /// systems nix skipped, e.g. `["legacyPackages", "x86_64-linux"]`
+    /// without `--legacy`. They show up above without any derivations.
+    /// nix prints them as empty attribute sets, so systems that really
+    /// are empty end up here too.
+    pub omitted: Vec<Vec<String>>,
+}
+
+impl FlakeInfo {
+    pub fn for_system(&self, sys: &str) -> IndividualFlakeInfos {
+        IndividualFlakeInfos {
+            apps: self.apps.get(sys).cloned().unwrap_or_default(),
+            checks: self.checks.get(sys).cloned().unwrap_or_default(),
+            dev_shells: self.dev_shells.get(sys).cloned().unwrap_or_default(),
+            formatter: self.formatter.get(sys).cloned(),
+            legacy_packages: self.legacy_packages.get(sys).cloned().unwrap_or_default(),
+            packages: self.packages.get(sys).cloned().unwrap_or_default(),
+            hydra_jobs: self
+                .hydra_jobs
+                .iter()
+                .filter(|job| job.system() == Some(sys))
+                .cloned()
+                .collect(),
+        }
+    }
+    /// Walk into a custom output, e.g. `["deploy", "nodes", "box"]`.
+    pub fn other_output(&self, path: &[&str]) -> Option<&OutputNode> {
+        let (output, rest) = path.split_first()?;
+        self.other_outputs.get(*output)?.get(rest)
+    }
+    pub fn for_current_system(&self) -> Result<IndividualFlakeInfos, FlakeShowError> {
+        self.for_current_system_with(&NixBinary::default())
+    }
+
+    /// Like [`FlakeInfo::for_current_system`], asking `nix` which system
+    /// it builds for.
+    pub fn for_current_system_with(
+        &self,
+        nix: &NixBinary,
+    ) -> Result<IndividualFlakeInfos, FlakeShowError> {
+        Ok(self.for_system(&current_nix_system_with(nix)?))
+    }
+
+    pub fn from_stdout(v: &[u8]) -> Result<Self, FlakeShowError> {
+        serde_json::from_slice::<FlakeShowOutput>(v)
+            .map(Into::into)
+            .map_err(|err| FlakeShowError::json(err, v))
+    }
+}
+
+fn derivations_by_system(
+    output: &str,
+    anatomy: BTreeMap<String, OutputNode>,
+) -> BTreeMap<String, Vec<Derivation>> {
+    anatomy
+        .into_iter()
+        .map(|(arch, node)| {
+            let derivs = node
+                .derivations(&[output, &arch])
+                .into_iter()
+                .map(|(_, deriv)| deriv)
+                .collect();
+            (arch, derivs)
+        })
+        .collect()
+}
+
+/// The systems of a per-system output that nix didn't evaluate.
+fn omitted_systems<'a>(
+    output: &'a str,
+    anatomy: &'a BTreeMap<String, OutputNode>,
+) -> impl Iterator<Item = Vec<String>> + 'a {
+    anatomy
+        .iter()
+        .filter(|(_, node)| match node {
+            OutputNode::Omitted => true,
+            // what nix actually prints for a system it skipped
+            OutputNode::Attrs(children) => children.is_empty(),
+            _ => false,
+        })
+        .map(move |(arch, _)| vec![output.to_string(), arch.clone()])
+}
+
+fn is_derivation(node: &OutputNode) -> bool {
+    matches!(node, OutputNode::Leaf { name: Some(_), .. })
+}
+
+fn is_app(node: &OutputNode) -> bool {
+    matches!(node, OutputNode::Leaf { .. })
+}
+
+/// What is left of `node` once the leaves `taken` turned into entries are
+/// removed, `None` if nothing is.
+fn leftover(node: &OutputNode, taken: fn(&OutputNode) -> bool) -> Option<OutputNode> {
+    match node {
+        OutputNode::Attrs(children) => {
+            let children: BTreeMap<_, _> = children
+                .iter()
+                .filter_map(|(attr, child)| Some((attr.clone(), leftover(child, taken)?)))
+                .collect();
+            (!children.is_empty()).then_some(OutputNode::Attrs(children))
+        }
+        leaf if taken(leaf) => None,
+        leaf => Some(leaf.clone()),
+    }
+}
+
+/// Like [`leftover`] for a per-system output, leaving out the systems nix
+/// omitted, which are recorded on their own.
+fn per_system_leftover(
+    anatomy: &BTreeMap<String, OutputNode>,
+    taken: fn(&OutputNode) -> bool,
+) -> Option<OutputNode> {
+    let systems: BTreeMap<_, _> = anatomy
+        .iter()
+        .filter(|(_, node)| !matches!(node, OutputNode::Omitted))
+        .filter_map(|(arch, node)| Some((arch.clone(), leftover(node, taken)?)))
+        .collect();
+    (!systems.is_empty()).then_some(OutputNode::Attrs(systems))
+}
+
+fn configurations(configs: BTreeMap<String, TypedLeaf>) -> Vec<Configuration> {
+    configs
+        .into_iter()
+        .map(|(name, leaf)| Configuration {
+            name,
+            kind: leaf.r#type,
+        })
+        .collect()
+}
+
+impl From<FlakeShowOutput> for FlakeInfo {
+    fn from(value: FlakeShowOutput) -> Self {
+        let apps = nodes(value.apps);
+        let checks = nodes(value.checks);
+        let dev_shells = nodes(value.dev_shells);
+        let formatter = nodes(value.formatter);
+        let legacy_packages = nodes(value.legacy_packages);
+        let packages = nodes(value.packages);
+
+        let omitted = [
+            ("apps", &apps),
+            ("checks", &checks),
+            ("devShells", &dev_shells),
+            ("formatter", &formatter),
+            ("legacyPackages", &legacy_packages),
+            ("packages", &packages),
+        ]
+        .into_iter()
+        .flat_map(|(output, anatomy)| omitted_systems(output, anatomy))
+        .collect();
+
+        // anything nix reported that doesn't fit the entries above, e.g.
+        // `legacyPackages.x86_64-linux.lib`, is kept as a tree of its own.
+        let mut other_outputs = nodes(value.other);
+        let per_system = [
+            ("apps", &apps, is_app as fn(&OutputNode) -> bool),
+            ("checks", &checks, is_derivation),
+            ("devShells", &dev_shells, is_derivation),
+            ("formatter", &formatter, is_derivation),
+            ("legacyPackages", &legacy_packages, is_derivation),
+            ("packages", &packages, is_derivation),
+        ];
+        for (output, anatomy, taken) in per_system {
+            if let Some(node) = per_system_leftover(anatomy, taken) {
+                other_outputs.insert(output.to_string(), node);
+            }
+        }
+        let hydra_jobs = value.hydra_jobs.map(OutputNode::from);
+        if let Some(node) = hydra_jobs
+            .as_ref()
+            .and_then(|node| leftover(node, is_derivation))
+        {
+            other_outputs.insert("hydraJobs".to_string(), node);
+        }
+
+        let apps = apps
+            .into_iter()
+            .map(|(arch, node)| {
+                let apps = node
+                    .leaves()
+                    .into_iter()
+                    .filter_map(|(path, leaf)| match leaf {
+                        OutputNode::Leaf { description, .. } => Some(App {
+                            invocation: path.join("."),
+                            description: description.clone(),
+                        }),
+                        _ => None,
+                    })
+                    .collect();
+                (arch, apps)
+            })
+            .collect();
+
+        let formatter = formatter
+            .into_iter()
+            .filter_map(|(arch, node)| {
+                let (_, mut deriv) = node.derivations(&["formatter", &arch]).into_iter().next()?;
+                deriv.invocation = "formatter".to_string();
+                Some((arch, deriv))
+            })
+            .collect();
+
+        let hydra_jobs = hydra_jobs
+            .map(|node| node.derivations(&["hydraJobs"]))
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(path, derivation)| HydraJob { path, derivation })
+            .collect();
+
+        let templates = value
+            .templates
+            .into_iter()
+            .map(|(key, v)| (key, v.description))
+            .collect();
+
+        FlakeInfo {
+            apps,
+            checks: derivations_by_system("checks", checks),
+            dev_shells: derivations_by_system("devShells", dev_shells),
+            packages: derivations_by_system("packages", packages),
+            legacy_packages: derivations_by_system("legacyPackages", legacy_packages),
+            formatter,
+            hydra_jobs,
+            overlays: value
+                .overlays
+                .into_iter()
+                .map(|(name, leaf)| Overlay {
+                    name,
+                    kind: leaf.r#type,
+                })
+                .collect(),
+            nixos_modules: value
+                .nixos_modules
+                .into_iter()
+                .map(|(name, leaf)| NixosModule {
+                    name,
+                    kind: leaf.r#type,
+                })
+                .collect(),
+            nixos_configurations: configurations(value.nixos_configurations),
+            darwin_configurations: configurations(value.darwin_configurations),
+            home_configurations: configurations(value.home_configurations),
+            lib: value.lib.map(OutputNode::from),
+            templates,
+            other_outputs,
+            omitted,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_schema() {
+        let info = FlakeInfo::from_stdout(
+            br#"{
+                "apps": {"x86_64-linux": {"default": {"type": "app", "description": "run it"}}},
+                "checks": {"x86_64-linux": {"fmt": {"name": "fmt-check", "type": "derivation"}}},
+                "formatter": {"x86_64-linux": {"name": "alejandra-3.0.0", "type": "derivation"}},
+                "legacyPackages": {
+                    "x86_64-linux": {
+                        "hello": {"name": "hello-2.12", "type": "derivation"},
+                        "python3Packages": {"requests": {"name": "requests-2.31", "type": "derivation"}}
+                    },
+                    "aarch64-darwin": {}
+                },
+                "hydraJobs": {"tests": {"x86_64-linux": {"name": "tests", "type": "derivation"}}},
+                "overlays": {"default": {"type": "nixpkgs-overlay"}},
+                "nixosModules": {"default": {"type": "nixos-module"}},
+                "nixosConfigurations": {"box": {"type": "nixos-configuration"}},
+                "darwinConfigurations": {"mac": {"type": "unknown"}},
+                "homeConfigurations": {"me": {"type": "unknown"}},
+                "lib": {"type": "unknown"}
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(info.lib, Some(OutputNode::Unknown));
+        assert_eq!(info.overlays[0].name, "default");
+        assert_eq!(info.overlays[0].kind, "nixpkgs-overlay");
+        assert_eq!(info.nixos_modules[0].name, "default");
+        assert_eq!(info.nixos_modules[0].kind, "nixos-module");
+        assert_eq!(info.nixos_configurations[0].kind, "nixos-configuration");
+        assert_eq!(info.darwin_configurations[0].name, "mac");
+        assert_eq!(info.home_configurations[0].name, "me");
+
+        let linux = info.for_system("x86_64-linux");
+        assert_eq!(linux.apps[0].description.as_deref(), Some("run it"));
+        assert_eq!(linux.checks[0].invocation, "fmt");
+        assert_eq!(linux.formatter.unwrap().name, "alejandra-3.0.0");
+        assert_eq!(linux.hydra_jobs[0].path, ["tests", "x86_64-linux"]);
+        assert_eq!(
+            linux.hydra_jobs[0].derivation.attr_path,
+            ["hydraJobs", "tests", "x86_64-linux"]
+        );
+
+        let legacy: Vec<_> = linux
+            .legacy_packages
+            .iter()
+            .map(|d| d.invocation.as_str())
+            .collect();
+        assert_eq!(legacy, ["hello", "python3Packages.requests"]);
+        let requests = linux
+            .legacy_packages
+            .iter()
+            .find(|d| d.invocation == "python3Packages.requests")
+            .unwrap();
+        assert_eq!(
+            requests.attr_path,
+            [
+                "legacyPackages",
+                "x86_64-linux",
+                "python3Packages",
+                "requests"
+            ]
+        );
+
+        assert!(info.for_system("aarch64-darwin").legacy_packages.is_empty());
+    }
+
+    #[test]
+    fn derivations_are_in_attribute_order() {
+        let stdout = br#"{
+            "packages": {
+                "x86_64-linux": {
+                    "zlib": {"name": "zlib-1.3", "type": "derivation"},
+                    "default": {"name": "hello-2.12.1", "type": "derivation"},
+                    "hello": {"name": "hello-2.12.1", "type": "derivation"}
+                },
+                "aarch64-darwin": {"type": "omitted"}
+            },
+            "hydraJobs": {
+                "tests": {"x86_64-linux": {"name": "tests", "type": "derivation"}},
+                "build": {"x86_64-linux": {"name": "build", "type": "derivation"}}
+            }
+        }"#;
+        let info = FlakeInfo::from_stdout(stdout).unwrap();
+
+        assert_eq!(
+            info.packages.keys().collect::<Vec<_>>(),
+            ["aarch64-darwin", "x86_64-linux"]
+        );
+        let packages: Vec<_> = info.packages["x86_64-linux"]
+            .iter()
+            .map(|d| d.invocation.as_str())
+            .collect();
+        assert_eq!(packages, ["default", "hello", "zlib"]);
+        let jobs: Vec<_> = info
+            .hydra_jobs
+            .iter()
+            .map(|job| job.path.join("."))
+            .collect();
+        assert_eq!(jobs, ["build.x86_64-linux", "tests.x86_64-linux"]);
+        assert_eq!(FlakeInfo::from_stdout(stdout).unwrap(), info);
+    }
+
+    #[test]
+    fn other_outputs() {
+        let info = FlakeInfo::from_stdout(
+            br#"{
+                "packages": {"aarch64-darwin": {"type": "omitted"}},
+                "deploy": {"nodes": {"box": {"type": "unknown"}}},
+                "herculesCI": {"type": "unknown"},
+                "colmena": {"box": {"type": "omitted"}, "meta": {"type": "nixos-module"}}
+            }"#,
+        )
+        .unwrap();
+
+        assert!(info.for_system("aarch64-darwin").packages.is_empty());
+        assert_eq!(info.other_outputs.len(), 3);
+        assert_eq!(
+            info.other_output(&["herculesCI"]),
+            Some(&OutputNode::Unknown)
+        );
+        assert_eq!(
+            info.other_output(&["deploy", "nodes", "box"]),
+            Some(&OutputNode::Unknown)
+        );
+        assert_eq!(
+            info.other_output(&["colmena", "box"]),
+            Some(&OutputNode::Omitted)
+        );
+        assert_eq!(
+            info.other_output(&["colmena", "meta"]),
+            Some(&OutputNode::Leaf {
+                kind: "nixos-module".to_string(),
+                name: None,
+                description: None,
+            })
+        );
+        assert_eq!(info.other_output(&["deploy", "missing"]), None);
+        assert_eq!(info.other_outputs["deploy"].leaves().len(), 1);
+    }
+}
@@ -1,11 +1,62 @@
-use std::process::Stdio;
-
-use bstr::ByteSlice;
+pub use backend::{
+    NixBackend, NixInvocation, NixOutput, ProcessBackend, ReplayBackend, StderrLineHandler,
+};
+pub use build::{BuildResult, Installable, NixBuildBuilder};
+pub use catalog::CatalogRenderer;
+pub use check::{CheckReport, CheckResult, NixFlakeCheckBuilder};
+pub use dev_env::{DevEnv, DevEnvVariable, NixPrintDevEnvBuilder};
+pub use diff::{
+    Change, DerivationChange, DerivationSummary, FlakeInfoDiff, LockDiff, LockedInputChange,
+    LockedInputSummary, SystemDiff, TemplatesDiff,
+};
+pub use error::FlakeShowError;
+pub use flake_lock::{FlakeLock, LockInput, LockNode, LockedRef};
+pub use flake_ref::{FlakeRef, FlakeRefKind};
+pub use init::{NixFlakeInitBuilder, TemplateInstance};
 pub use internal_flake_show_output::FlakeInfo;
 pub use internal_flake_show_output::IndividualFlakeInfos;
+pub use internal_flake_show_output::{
+    App, Configuration, Derivation, HydraJob, NixosModule, OutputNode, Overlay,
+};
+pub use log::{ActivityType, LogField, NixLogEvent, ResultType, Verbosity};
+pub use meta::{DerivationMeta, License, Maintainer, NixDerivationMetaBuilder};
+pub use metadata::{FlakeMetadata, NixFlakeMetadataBuilder};
+pub use nix_binary::{NixBinary, NIX_BIN_ENV};
+#[cfg(feature = "schema")]
+pub use schema::FLAKE_INFO_SCHEMA_VERSION;
+pub use template::{NixFlakeTemplatesBuilder, Template};
+pub use tree::TreeRenderer;
+pub use update::{NixFlakeLockBuilder, NixFlakeUpdateBuilder};
+
+mod backend;
+mod build;
+mod catalog;
+mod check;
+mod common;
+mod dev_env;
+mod diff;
+mod error;
+mod flake_lock;
+mod flake_ref;
+mod init;
+mod internal_flake_show_output;
+mod log;
+mod meta;
+mod metadata;
+mod nix_binary;
+mod process;
+#[cfg(feature = "schema")]
+mod schema;
+mod template;
+#[cfg(test)]
+mod test_util;
+mod tree;
+mod update;
+
+use common::{common_setters, CommonArgs};
 
 pub fn nix_cmd() -> std::process::Command {
-    std::process::Command::new("/nix/var/nix/profiles/default/bin/nix")
+    NixBinary::default().command()
 }
 
 #[derive(Debug)]
@@ -21,26 +72,18 @@ pub struct NixFlakeShowBuilder {
     all_systems: bool,
     json: bool,
     legacy: bool,
-    impure: bool,
-    recreate_lock_file: bool,
-    debug: bool,
-    refresh: bool,
-    verbosity_level: usize,
-    log_format: Option<NixFlakeLogFormat>,
-    url: Option<std::path::PathBuf>,
+    url: Option<FlakeRef>,
+    common: CommonArgs,
 }
 
 impl NixFlakeShowBuilder {
+    common_setters!();
+
     pub fn all_systems(mut self, all_systems: bool) -> Self {
         self.all_systems = all_systems;
         self
     }
 
-    pub fn refresh(mut self, refresh: bool) -> Self {
-        self.refresh = refresh;
-        self
-    }
-
     pub fn json(mut self, json: bool) -> Self {
         self.json = json;
         self
@@ -51,52 +94,46 @@ impl NixFlakeShowBuilder {
         self
     }
 
-    pub fn impure(mut self, impure: bool) -> Self {
-        self.impure = impure;
-        self
-    }
-
-    pub fn recreate_lock_file(mut self, recreate_lock_file: bool) -> Self {
-        self.recreate_lock_file = recreate_lock_file;
-        self
-    }
-
-    pub fn debug(mut self, debug: bool) -> Self {
-        self.debug = debug;
-        self
-    }
-
-    pub fn verbosity_level(mut self, verbosity_level: usize) -> Self {
-        self.verbosity_level = verbosity_level;
+    pub fn url(mut self, url: impl Into<FlakeRef>) -> Self {
+        self.url = Some(url.into());
         self
     }
 
-    pub fn log_format(mut self, log_format: Option<NixFlakeLogFormat>) -> Self {
-        self.log_format = log_format;
-        self
+    pub fn into_structured(mut self) -> Result<FlakeInfo, FlakeShowError> {
+        let runner = self.common.take_runner();
+        let stdout = runner.run(&self.json(true).all_systems(true).build())?;
+        FlakeInfo::from_stdout(&stdout)
     }
 
-    pub fn url(mut self, url: std::path::PathBuf) -> Self {
-        self.url = Some(url);
-        self
+    /// The outputs for the system nix builds for, asked of the same nix
+    /// binary and backend the flake is shown with.
+    pub fn into_current_system(mut self) -> Result<IndividualFlakeInfos, FlakeShowError> {
+        let nix = self.common.nix.clone().unwrap_or_default();
+        let runner = self.common.take_runner();
+        let system_runner = runner.sibling();
+        let stdout = runner.run(&self.json(true).all_systems(true).build())?;
+        let info = FlakeInfo::from_stdout(&stdout)?;
+        let system = system_runner.run(&current_nix_system_cmd(&nix))?;
+        Ok(info.for_system(&String::from_utf8(system).map_err(FlakeShowError::Utf8)?))
     }
 
-    pub fn into_structured(self) -> Result<Option<FlakeInfo>, std::io::Error> {
-        let output = self.json(true).all_systems(true).build().output()?;
-
-        if output.status.success() {
-            Ok(Some(FlakeInfo::from_stdout(&output.stdout)))
-        } else {
-            Ok(None)
-        }
+    /// Like [`NixFlakeShowBuilder::into_structured`], but without blocking the
+    /// executor. Dropping the future kills the nix process. A custom
+    /// [`NixBackend`] is run on tokio's blocking thread pool.
+    #[cfg(feature = "tokio")]
+    pub async fn into_structured_async(mut self) -> Result<FlakeInfo, FlakeShowError> {
+        let runner = self.common.take_runner();
+        let stdout = runner
+            .run_async(self.json(true).all_systems(true).build())
+            .await?;
+        FlakeInfo::from_stdout(&stdout)
     }
 
     pub fn build(self) -> std::process::Command {
-        let mut cmd = nix_cmd();
-        cmd.arg("flake").arg("show");
+        let mut cmd = self.common.command(&["flake", "show"]);
 
         if let Some(url) = self.url {
-            cmd.arg(url);
+            cmd.arg(url.to_string());
         }
 
         if self.all_systems {
@@ -111,161 +148,36 @@ impl NixFlakeShowBuilder {
             cmd.arg("--legacy");
         }
 
-        if self.impure {
-            cmd.arg("--impure");
-        }
-
-        if self.recreate_lock_file {
-            cmd.arg("--recreate-lock-file");
-        }
-
-        if self.debug {
-            cmd.arg("--debug");
-        }
-
-        if self.verbosity_level > 0 {
-            cmd.arg(format!("-{}", "v".repeat(self.verbosity_level)));
-        }
-
-        if let Some(log_format) = self.log_format {
-            cmd.arg("--log-format");
-            let log_format_str = match log_format {
-                NixFlakeLogFormat::Raw => "raw",
-                NixFlakeLogFormat::InternalJson => "internal-json",
-                NixFlakeLogFormat::Bar => "bar",
-                NixFlakeLogFormat::BarWithLogs => "bar-with-logs",
-            };
-            cmd.arg(log_format_str);
-        }
-
-        cmd.stdout(Stdio::piped());
-        cmd.stderr(Stdio::inherit());
+        self.common.push_flags(&mut cmd);
         cmd
     }
 }
-pub use internal_flake_show_output::Derivation;
-
-mod internal_flake_show_output {
-    use std::collections::HashMap;
-
-    use serde::{Deserialize, Serialize};
-
-    use crate::current_nix_system;
-
-    #[derive(Serialize, Deserialize)]
-    #[serde(rename_all = "camelCase")]
-    pub struct FlakeShowOutput {
-        // from architecture to named fields
-        #[serde(default)]
-        dev_shells: HashMap<String, HighLevelFieldAnatomy>,
-        #[serde(default)]
-        packages: HashMap<String, HighLevelFieldAnatomy>,
-        #[serde(default)]
-        templates: HashMap<String, TemplateDescription>,
-    }
-
-    #[derive(Serialize, Deserialize)]
-    pub struct TemplateDescription {
-        description: String,
-    }
-
-    #[derive(Serialize, Deserialize)]
-    pub struct HighLevelFieldAnatomy {
-        #[serde(flatten)]
-        // from named fields to derivation details
-        names: HashMap<String, FlakeAnatomyDetail>,
-    }
-    #[derive(Serialize, Deserialize)]
-    pub struct FlakeAnatomyDetail {
-        pub name: String,
-        pub r#type: String,
-        pub description: Option<String>,
-    }
-
-    #[derive(Debug, Clone)]
-    pub struct Derivation {
-        pub name: String,
-        pub kind: String,
-        pub description: Option<String>,
-        pub invocation: String,
-    }
-
-    #[derive(Debug, Clone)]
-    pub struct IndividualFlakeInfos {
-        pub dev_shells: Vec<Derivation>,
-        pub packages: Vec<Derivation>,
-    }
-
-    #[derive(Debug, Clone)]
-    pub struct FlakeInfo {
-        /// from architecture to derivation
-        pub dev_shells: HashMap<String, Vec<Derivation>>,
-        pub packages: HashMap<String, Vec<Derivation>>,
-        /// from template name to template description.
-        pub templates: HashMap<String, String>,
-    }
-
-    impl FlakeInfo {
-        pub fn for_system(&self, sys: &str) -> IndividualFlakeInfos {
-            IndividualFlakeInfos {
-                dev_shells: self.dev_shells.get(sys).cloned().unwrap_or_default(),
-                packages: self.packages.get(sys).cloned().unwrap_or_default(),
-            }
-        }
-        pub fn for_current_system(&self) -> IndividualFlakeInfos {
-            self.for_system(&current_nix_system())
-        }
-
-        pub fn from_stdout(v: &[u8]) -> Self {
-            serde_json::from_slice::<FlakeShowOutput>(v).unwrap().into()
-        }
-    }
-
-    impl From<FlakeShowOutput> for FlakeInfo {
-        fn from(value: FlakeShowOutput) -> Self {
-            let mut devs = HashMap::new();
-            for (arch, anat) in value.dev_shells {
-                let derivs: &mut Vec<Derivation> = devs.entry(arch).or_default();
-                for (invok, details) in anat.names {
-                    derivs.push(Derivation {
-                        name: details.name,
-                        kind: details.r#type,
-                        description: details.description,
-                        invocation: invok,
-                    });
-                }
-            }
+pub fn current_nix_system() -> Result<String, FlakeShowError> {
+    current_nix_system_with(&NixBinary::default())
+}
 
-            let mut packages = HashMap::new();
-            for (arch, anat) in value.packages {
-                let derivs: &mut Vec<Derivation> = packages.entry(arch).or_default();
-                for (invok, details) in anat.names {
-                    derivs.push(Derivation {
-                        name: details.name,
-                        kind: details.r#type,
-                        description: details.description,
-                        invocation: invok,
-                    });
-                }
-            }
+pub fn current_nix_system_with(nix: &NixBinary) -> Result<String, FlakeShowError> {
+    String::from_utf8(process::run_captured(
+        current_nix_system_cmd(nix),
+        None,
+        None,
+    )?)
+    .map_err(FlakeShowError::Utf8)
+}
 
-            let templates = value
-                .templates
-                .into_iter()
-                .map(|(key, v)| (key, v.description))
-                .collect();
+#[cfg(feature = "tokio")]
+pub async fn current_nix_system_async() -> Result<String, FlakeShowError> {
+    current_nix_system_with_async(&NixBinary::default()).await
+}
 
-            FlakeInfo {
-                dev_shells: devs,
-                templates,
-                packages,
-            }
-        }
-    }
+#[cfg(feature = "tokio")]
+pub async fn current_nix_system_with_async(nix: &NixBinary) -> Result<String, FlakeShowError> {
+    String::from_utf8(process::run_captured_async(current_nix_system_cmd(nix), None, None).await?)
+        .map_err(FlakeShowError::Utf8)
 }
 
-pub fn current_nix_system() -> String {
-    let mut cmd = nix_cmd();
+fn current_nix_system_cmd(nix: &NixBinary) -> std::process::Command {
+    let mut cmd = nix.command();
     cmd.args([
         "eval",
         "--impure",
@@ -273,33 +185,222 @@ pub fn current_nix_system() -> String {
         "--expr",
         "builtins.currentSystem",
     ]);
-
-    cmd.output().unwrap().stdout.as_bstr().to_string()
+    cmd
 }
 pub fn flake_show() -> NixFlakeShowBuilder {
     NixFlakeShowBuilder::default()
 }
 
+pub fn flake_check() -> NixFlakeCheckBuilder {
+    NixFlakeCheckBuilder::default()
+}
+
+pub fn flake_update() -> NixFlakeUpdateBuilder {
+    NixFlakeUpdateBuilder::default()
+}
+
+pub fn flake_lock() -> NixFlakeLockBuilder {
+    NixFlakeLockBuilder::default()
+}
+
+/// `nix flake init`, instantiating a template in the current directory.
+pub fn flake_init() -> NixFlakeInitBuilder {
+    NixFlakeInitBuilder::default()
+}
+
+/// `nix flake new`, instantiating a template in a new directory `dir`.
+pub fn flake_new(dir: impl Into<std::path::PathBuf>) -> NixFlakeInitBuilder {
+    NixFlakeInitBuilder::new_flake(dir.into())
+}
+
+/// The templates of a flake with their paths, welcome texts and files.
+pub fn flake_templates() -> NixFlakeTemplatesBuilder {
+    NixFlakeTemplatesBuilder::default()
+}
+
+/// Version, license, maintainers and more for every derivation of a
+/// [`FlakeInfo`], in a single `nix eval`.
+pub fn derivation_meta() -> NixDerivationMetaBuilder {
+    NixDerivationMetaBuilder::default()
+}
+
+pub fn flake_metadata() -> NixFlakeMetadataBuilder {
+    NixFlakeMetadataBuilder::default()
+}
+
+pub fn nix_build() -> NixBuildBuilder {
+    NixBuildBuilder::default()
+}
+
+pub fn print_dev_env() -> NixPrintDevEnvBuilder {
+    NixPrintDevEnvBuilder::default()
+}
+
 #[cfg(test)]
 mod tests {
 
     use super::*;
 
     #[test]
+    #[ignore = "needs nix and a flake on the author's machine"]
     fn test_local() {
         let structured = flake_show()
-            .url("/Users/andy/Documents/colby/jerzy-work/surveyConformal".into())
-            .into_structured();
+            .url(std::path::Path::new(
+                "/Users/andy/Documents/colby/jerzy-work/surveyConformal",
+            ))
+            .into_current_system();
 
-        dbg!(structured.unwrap().unwrap().for_current_system());
+        dbg!(structured.unwrap());
     }
     #[test]
+    #[ignore = "needs nix and network access"]
     fn test_nixos() {
         let structured = flake_show()
             .refresh(true)
-            .url("github:NixOS/templates".into())
+            .url(FlakeRef::github("NixOS", "templates"))
             .into_structured();
 
-        dbg!(structured.unwrap().unwrap().templates);
+        dbg!(structured.unwrap().templates);
+    }
+
+    #[test]
+    fn replayed_templates() {
+        let backend = ReplayBackend::new().record(
+            [
+                "flake",
+                "show",
+                "github:NixOS/templates",
+                "--all-systems",
+                "--json",
+            ],
+            NixOutput::success(
+                r#"{"templates": {"rust": {"description": "Rust template, using Naersk", "type": "template"}}}"#,
+            ),
+        );
+
+        let structured = flake_show()
+            .url(FlakeRef::github("NixOS", "templates"))
+            .backend(backend)
+            .into_structured()
+            .unwrap();
+
+        assert_eq!(structured.templates["rust"], "Rust template, using Naersk");
+    }
+
+    #[test]
+    fn current_system_goes_through_the_backend() {
+        let backend = ReplayBackend::new()
+            .record(
+                ["flake", "show", "--all-systems", "--json"],
+                NixOutput::success(
+                    r#"{"packages": {
+                        "aarch64-darwin": {"hello": {"name": "hello-2.12.1", "type": "derivation"}},
+                        "x86_64-linux": {"tool": {"name": "tool-1.0", "type": "derivation"}}
+                    }}"#,
+                ),
+            )
+            .record(
+                [
+                    "eval",
+                    "--impure",
+                    "--raw",
+                    "--expr",
+                    "builtins.currentSystem",
+                ],
+                NixOutput::success("aarch64-darwin"),
+            );
+
+        let system = flake_show()
+            .nix_binary(NixBinary::new("/opt/nix/bin/nix"))
+            .backend(backend)
+            .into_current_system()
+            .unwrap();
+
+        assert_eq!(system.packages[0].invocation, "hello");
+    }
+
+    #[test]
+    fn replayed_failure_keeps_stderr() {
+        let err = flake_show()
+            .backend(ReplayBackend::new().fallback(NixOutput::failure(
+                1,
+                "error: path '/nowhere' does not contain a 'flake.nix'\n",
+            )))
+            .into_structured()
+            .unwrap_err();
+
+        match err {
+            FlakeShowError::NonZeroExit { code, stderr } => {
+                assert_eq!(code, Some(1));
+                assert!(stderr.contains("does not contain a 'flake.nix'"));
+            }
+            other => panic!("unexpected error {other:?}"),
+        }
+    }
+
+    #[test]
+    fn malformed_output_is_an_error() {
+        let err = FlakeInfo::from_stdout(br#"{"packages": 3}"#).unwrap_err();
+        assert!(
+            matches!(err, FlakeShowError::Json { offset: 13, .. }),
+            "{err:?}"
+        );
+    }
+
+    #[test]
+    fn missing_nix_is_reported() {
+        let err = flake_show()
+            .nix_binary(NixBinary::new("/definitely/not/a/nix"))
+            .into_structured()
+            .unwrap_err();
+        assert!(matches!(err, FlakeShowError::NixNotFound), "{err:?}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn log_events_are_delivered() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = crate::test_util::scratch_dir("log");
+        let nix = dir.join("nix");
+        std::fs::write(
+            &nix,
+            r#"#!/bin/sh
+echo '@nix {"action":"start","id":1,"level":3,"parent":0,"text":"fetching","type":112,"fields":[]}' >&2
+echo '@nix {"action":"stop","id":1}' >&2
+echo '{}'
+"#,
+        )
+        .unwrap();
+        std::fs::set_permissions(&nix, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let info = flake_show()
+            .nix_binary(NixBinary::new(&nix))
+            .log_events_to(tx)
+            .into_structured();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(info.unwrap().packages.is_empty());
+        let events: Vec<_> = rx.iter().collect();
+        assert!(matches!(
+            events[0],
+            NixLogEvent::Start {
+                activity: ActivityType::FetchTree,
+                ..
+            }
+        ));
+        assert_eq!(events[1], NixLogEvent::Stop { id: 1 });
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn missing_nix_is_reported_async() {
+        let err = flake_show()
+            .nix_binary(NixBinary::new("/definitely/not/a/nix"))
+            .into_structured_async()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, FlakeShowError::NixNotFound), "{err:?}");
     }
 }
@@ -0,0 +1,329 @@
+use std::fmt;
+
+use serde::Deserialize;
+
+/// One structured line of `--log-format internal-json` output, which nix
+/// prints to stderr prefixed with `@nix `.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NixLogEvent {
+    /// An activity (a download, a build, fetching an input, ...) started.
+    Start {
+        id: u64,
+        /// the activity this one is part of, `0` for none.
+        parent: u64,
+        level: Verbosity,
+        activity: ActivityType,
+        text: String,
+        fields: Vec<LogField>,
+    },
+    /// The activity with this id finished.
+    Stop { id: u64 },
+    /// An activity reported progress, a build log line, a phase, ...
+    Result {
+        id: u64,
+        result: ResultType,
+        fields: Vec<LogField>,
+    },
+    /// A plain log message, such as a warning or the final error.
+    Msg { level: Verbosity, msg: String },
+}
+
+impl NixLogEvent {
+    /// Parse a single line of stderr. Lines that aren't `@nix` events, or
+    /// that nix produced in a shape this crate doesn't know, yield `None`.
+    pub fn parse(line: &[u8]) -> Option<Self> {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let json = line.strip_prefix(b"@nix ")?;
+        serde_json::from_slice::<RawEvent>(json)
+            .ok()
+            .map(Into::into)
+    }
+
+    /// The activity this event belongs to, if any.
+    pub fn id(&self) -> Option<u64> {
+        match self {
+            NixLogEvent::Start { id, .. }
+            | NixLogEvent::Stop { id }
+            | NixLogEvent::Result { id, .. } => Some(*id),
+            NixLogEvent::Msg { .. } => None,
+        }
+    }
+}
+
+/// A field attached to an activity or result, nix sends either integers
+/// or strings.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum LogField {
+    Int(u64),
+    String(String),
+}
+
+impl fmt::Display for LogField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogField::Int(n) => write!(f, "{n}"),
+            LogField::String(s) => write!(f, "{s}"),
+        }
+    }
+}
+
+/// nix's verbosity levels, from `lvlError` to `lvlVomit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    Error,
+    Warn,
+    Notice,
+    Info,
+    Talkative,
+    Chatty,
+    Debug,
+    Vomit,
+}
+
+impl From<u64> for Verbosity {
+    fn from(value: u64) -> Self {
+        match value {
+            0 => Verbosity::Error,
+            1 => Verbosity::Warn,
+            2 => Verbosity::Notice,
+            3 => Verbosity::Info,
+            4 => Verbosity::Talkative,
+            5 => Verbosity::Chatty,
+            6 => Verbosity::Debug,
+            _ => Verbosity::Vomit,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActivityType {
+    Unknown,
+    CopyPath,
+    /// a download, e.g. of a tarball or a binary cache narinfo.
+    FileTransfer,
+    Realise,
+    CopyPaths,
+    Builds,
+    Build,
+    OptimiseStore,
+    VerifyPaths,
+    Substitute,
+    QueryPathInfo,
+    PostBuildHook,
+    BuildWaiting,
+    /// fetching a flake input.
+    FetchTree,
+    /// an activity type newer than this crate.
+    Other(u64),
+}
+
+impl From<u64> for ActivityType {
+    fn from(value: u64) -> Self {
+        match value {
+            0 => ActivityType::Unknown,
+            100 => ActivityType::CopyPath,
+            101 => ActivityType::FileTransfer,
+            102 => ActivityType::Realise,
+            103 => ActivityType::CopyPaths,
+            104 => ActivityType::Builds,
+            105 => ActivityType::Build,
+            106 => ActivityType::OptimiseStore,
+            107 => ActivityType::VerifyPaths,
+            108 => ActivityType::Substitute,
+            109 => ActivityType::QueryPathInfo,
+            110 => ActivityType::PostBuildHook,
+            111 => ActivityType::BuildWaiting,
+            112 => ActivityType::FetchTree,
+            other => ActivityType::Other(other),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultType {
+    FileLinked,
+    BuildLogLine,
+    UntrustedPath,
+    CorruptedPath,
+    SetPhase,
+    /// fields are done, expected, running and failed counts.
+    Progress,
+    SetExpected,
+    PostBuildLogLine,
+    FetchStatus,
+    /// a result type newer than this crate.
+    Other(u64),
+}
+
+impl From<u64> for ResultType {
+    fn from(value: u64) -> Self {
+        match value {
+            100 => ResultType::FileLinked,
+            101 => ResultType::BuildLogLine,
+            102 => ResultType::UntrustedPath,
+            103 => ResultType::CorruptedPath,
+            104 => ResultType::SetPhase,
+            105 => ResultType::Progress,
+            106 => ResultType::SetExpected,
+            107 => ResultType::PostBuildLogLine,
+            108 => ResultType::FetchStatus,
+            other => ResultType::Other(other),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "camelCase")]
+enum RawEvent {
+    Start {
+        id: u64,
+        #[serde(default)]
+        parent: u64,
+        #[serde(default)]
+        level: u64,
+        #[serde(rename = "type")]
+        activity: u64,
+        #[serde(default)]
+        text: String,
+        #[serde(default)]
+        fields: Vec<LogField>,
+    },
+    Stop {
+        id: u64,
+    },
+    Result {
+        id: u64,
+        #[serde(rename = "type")]
+        result: u64,
+        #[serde(default)]
+        fields: Vec<LogField>,
+    },
+    Msg {
+        level: u64,
+        msg: String,
+    },
+}
+
+impl From<RawEvent> for NixLogEvent {
+    fn from(value: RawEvent) -> Self {
+        match value {
+            RawEvent::Start {
+                id,
+                parent,
+                level,
+                activity,
+                text,
+                fields,
+            } => NixLogEvent::Start {
+                id,
+                parent,
+                level: level.into(),
+                activity: activity.into(),
+                text,
+                fields,
+            },
+            RawEvent::Stop { id } => NixLogEvent::Stop { id },
+            RawEvent::Result { id, result, fields } => NixLogEvent::Result {
+                id,
+                result: result.into(),
+                fields,
+            },
+            RawEvent::Msg { level, msg } => NixLogEvent::Msg {
+                level: level.into(),
+                msg,
+            },
+        }
+    }
+}
+
+/// nix colours its messages, even in internal-json logs.
+pub(crate) fn strip_ansi(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            if chars.next() == Some('[') {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Receives log events while nix is still running.
+pub(crate) struct NixLogEventHandler(pub(crate) Box<dyn FnMut(NixLogEvent) + Send>);
+
+impl fmt::Debug for NixLogEventHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("NixLogEventHandler")
+    }
+}
+
+impl NixLogEventHandler {
+    /// Adapt this handler to the raw stderr lines the process runner sees.
+    pub(crate) fn into_line_handler(mut self) -> crate::StderrLineHandler {
+        Box::new(move |line| {
+            if let Some(event) = NixLogEvent::parse(line) {
+                (self.0)(event)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_events() {
+        let start = br#"@nix {"action":"start","fields":["https://cache.nixos.org"],"id":42,"level":4,"parent":7,"text":"downloading 'https://cache.nixos.org'","type":101}"#;
+        assert_eq!(
+            NixLogEvent::parse(start),
+            Some(NixLogEvent::Start {
+                id: 42,
+                parent: 7,
+                level: Verbosity::Talkative,
+                activity: ActivityType::FileTransfer,
+                text: "downloading 'https://cache.nixos.org'".to_string(),
+                fields: vec![LogField::String("https://cache.nixos.org".to_string())],
+            })
+        );
+
+        let progress =
+            b"@nix {\"action\":\"result\",\"fields\":[10,100,0,0],\"id\":42,\"type\":105}\n";
+        assert_eq!(
+            NixLogEvent::parse(progress),
+            Some(NixLogEvent::Result {
+                id: 42,
+                result: ResultType::Progress,
+                fields: vec![
+                    LogField::Int(10),
+                    LogField::Int(100),
+                    LogField::Int(0),
+                    LogField::Int(0)
+                ],
+            })
+        );
+
+        assert_eq!(
+            NixLogEvent::parse(br#"@nix {"action":"stop","id":42}"#),
+            Some(NixLogEvent::Stop { id: 42 })
+        );
+        assert_eq!(
+            NixLogEvent::parse(br#"@nix {"action":"msg","level":1,"msg":"warning: dirty tree"}"#),
+            Some(NixLogEvent::Msg {
+                level: Verbosity::Warn,
+                msg: "warning: dirty tree".to_string()
+            })
+        );
+        assert_eq!(NixLogEvent::parse(b"warning: not json"), None);
+        assert_eq!(NixLogEvent::parse(b"@nix {\"action\":\"bogus\"}"), None);
+    }
+}
@@ -0,0 +1,339 @@
+use std::process::Command;
+
+use serde::Deserialize;
+
+use crate::common::{common_setters, CommonArgs};
+use crate::{Derivation, FlakeInfo, FlakeRef, FlakeShowError};
+
+/// Looks up every attribute path in `paths` below the flake's outputs and
+/// reduces the derivation found there to a [`DerivationMeta`], or `null`
+/// if it throws. Other evaluation errors, like a missing attribute, fail
+/// the whole eval. The `meta` conventions of nixpkgs are loose, so
+/// licenses, maintainers and homepages are normalised here.
+const META: &str = "outputs: let \
+    paths = builtins.fromJSON PATHS; \
+    list = x: if builtins.isList x then x else [ x ]; \
+    license = l: if builtins.isAttrs l \
+        then { spdxId = l.spdxId or null; fullName = l.fullName or null; free = l.free or true; } \
+        else { spdxId = null; fullName = toString l; free = true; }; \
+    maintainer = m: if builtins.isAttrs m \
+        then { name = m.name or null; email = m.email or null; github = m.github or null; } \
+        else { name = toString m; email = null; github = null; }; \
+    meta = drv: let m = drv.meta or { }; homepage = list (m.homepage or [ ]); in { \
+        version = drv.version or null; \
+        pname = drv.pname or null; \
+        outputs = drv.outputs or [ \"out\" ]; \
+        license = map license (list (m.license or [ ])); \
+        maintainers = map maintainer (list (m.maintainers or [ ])); \
+        platforms = builtins.filter builtins.isString (m.platforms or [ ]); \
+        mainProgram = m.mainProgram or null; \
+        broken = m.broken or false; \
+        homepage = if homepage == [ ] then null else builtins.head homepage; \
+    }; \
+    lookup = path: meta (builtins.foldl' (set: attr: set.${attr}) outputs path); \
+    safe = path: let result = builtins.tryEval (builtins.deepSeq (lookup path) (lookup path)); \
+        in if result.success then result.value else null; \
+    in map safe paths";
+
+/// The parts of a derivation's attributes and `meta` that `nix flake show`
+/// doesn't print, see [`NixDerivationMetaBuilder::enrich`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[cfg_attr(
+    feature = "schema",
+    derive(serde::Serialize, schemars::JsonSchema),
+    schemars(
+        description = "The parts of a derivation's attributes and `meta` that `nix flake show` doesn't print."
+    )
+)]
+pub struct DerivationMeta {
+    pub version: Option<String>,
+    pub pname: Option<String>,
+    /// e.g. `["out", "dev", "man"]`.
+    pub outputs: Vec<String>,
+    /// every license, even when `meta.license` is a single one.
+    pub license: Vec<License>,
+    pub maintainers: Vec<Maintainer>,
+    /// the systems it builds on, e.g. `x86_64-linux`. Platform patterns
+    /// given as attribute sets are left out.
+    pub platforms: Vec<String>,
+    /// the binary `nix run` starts.
+    pub main_program: Option<String>,
+    pub broken: bool,
+    /// the first one, when `meta.homepage` is a list.
+    pub homepage: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[cfg_attr(feature = "schema", derive(serde::Serialize, schemars::JsonSchema))]
+pub struct License {
+    /// e.g. `MIT`, `None` for licenses without an SPDX identifier.
+    #[cfg_attr(
+        feature = "schema",
+        schemars(description = "e.g. `MIT`, null for licenses without an SPDX identifier.")
+    )]
+    pub spdx_id: Option<String>,
+    pub full_name: Option<String>,
+    pub free: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "schema", derive(serde::Serialize, schemars::JsonSchema))]
+pub struct Maintainer {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub github: Option<String>,
+}
+
+impl DerivationMeta {
+    /// Parse the output of evaluating [`META`], one entry per attribute
+    /// path, `None` where evaluation failed.
+    pub fn from_stdout(v: &[u8]) -> Result<Vec<Option<Self>>, FlakeShowError> {
+        serde_json::from_slice(v).map_err(|err| FlakeShowError::json(err, v))
+    }
+}
+
+impl FlakeInfo {
+    /// Every derivation, in a fixed order.
+    fn derivations_mut(&mut self) -> impl Iterator<Item = &mut Derivation> {
+        let per_system = [
+            &mut self.checks,
+            &mut self.dev_shells,
+            &mut self.packages,
+            &mut self.legacy_packages,
+        ];
+        per_system
+            .into_iter()
+            .flat_map(|by_system| by_system.values_mut().flatten())
+            .chain(self.formatter.values_mut())
+            .chain(self.hydra_jobs.iter_mut().map(|job| &mut job.derivation))
+    }
+}
+
+/// A nix string literal for `s`.
+fn nix_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' | '\\' | '$' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\n' => out.push_str("\\n"),
+            _ => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// A single `nix eval` attaching [`DerivationMeta`] to every derivation of
+/// a [`FlakeInfo`].
+#[derive(Default, Debug)]
+pub struct NixDerivationMetaBuilder {
+    url: Option<FlakeRef>,
+    attr_paths: Vec<Vec<String>>,
+    common: CommonArgs,
+}
+
+impl NixDerivationMetaBuilder {
+    common_setters!();
+
+    /// The flake `info` was shown for, the one in the current directory by
+    /// default.
+    pub fn url(mut self, url: impl Into<FlakeRef>) -> Self {
+        self.url = Some(url.into());
+        self
+    }
+
+    /// Set [`Derivation::meta`] on every derivation of `info`. It stays
+    /// `None` for derivations that fail to evaluate.
+    ///
+    /// All derivations are evaluated at once. When that fails, as it does
+    /// for errors `builtins.tryEval` can't catch, each one is evaluated on
+    /// its own instead.
+    pub fn enrich(mut self, info: &mut FlakeInfo) -> Result<(), FlakeShowError> {
+        self.attr_paths = attr_paths(info);
+        if self.attr_paths.is_empty() {
+            return Ok(());
+        }
+        let runner = self.common.take_runner();
+        let single = runner.sibling();
+        let metas = match runner.run(&self.command(&self.attr_paths)) {
+            Ok(stdout) => DerivationMeta::from_stdout(&stdout)?,
+            Err(FlakeShowError::NonZeroExit { .. }) => {
+                let mut metas = Vec::with_capacity(self.attr_paths.len());
+                for path in &self.attr_paths {
+                    let meta = match single
+                        .sibling()
+                        .run(&self.command(std::slice::from_ref(path)))
+                    {
+                        Ok(stdout) => DerivationMeta::from_stdout(&stdout)?.pop().flatten(),
+                        Err(FlakeShowError::NonZeroExit { .. }) => None,
+                        Err(err) => return Err(err),
+                    };
+                    metas.push(meta);
+                }
+                metas
+            }
+            Err(err) => return Err(err),
+        };
+        attach(info, metas)
+    }
+
+    pub fn build(self) -> Command {
+        self.command(&self.attr_paths)
+    }
+
+    fn command(&self, attr_paths: &[Vec<String>]) -> Command {
+        let mut cmd = self.common.command(&["eval", "--json"]);
+
+        // a fragment starting with `.` is taken as is, and an empty
+        // attribute path is the flake's outputs.
+        let flake = self.url.clone().unwrap_or_else(|| FlakeRef::path("."));
+        let paths = serde_json::to_string(attr_paths).expect("strings serialize");
+        cmd.arg(format!("{flake}#."))
+            .arg("--apply")
+            .arg(META.replace("PATHS", &nix_string(&paths)));
+
+        self.common.push_flags(&mut cmd);
+        cmd
+    }
+}
+
+fn attr_paths(info: &mut FlakeInfo) -> Vec<Vec<String>> {
+    info.derivations_mut()
+        .map(|deriv| deriv.attr_path.clone())
+        .collect()
+}
+
+fn attach(info: &mut FlakeInfo, metas: Vec<Option<DerivationMeta>>) -> Result<(), FlakeShowError> {
+    let derivations: Vec<_> = info.derivations_mut().collect();
+    if derivations.len() != metas.len() {
+        return Err(FlakeShowError::MetaCountMismatch {
+            expected: derivations.len(),
+            got: metas.len(),
+        });
+    }
+    for (deriv, meta) in derivations.into_iter().zip(metas) {
+        deriv.meta = meta;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{derivation_meta, NixOutput, ReplayBackend};
+
+    const SHOW: &str = r#"{
+        "packages": {"x86_64-linux": {"hello": {"name": "hello-2.12.1", "type": "derivation"}}},
+        "checks": {"x86_64-linux": {"broken": {"name": "broken", "type": "derivation"}}}
+    }"#;
+
+    const META_JSON: &str = r#"[null, {
+        "version": "2.12.1",
+        "pname": "hello",
+        "outputs": ["out"],
+        "license": [{"spdxId": "GPL-3.0-or-later", "fullName": "GNU General Public License v3.0 or later", "free": true}],
+        "maintainers": [{"name": "Eelco Dolstra", "email": "edolstra@gmail.com", "github": "edolstra"}],
+        "platforms": ["x86_64-linux", "aarch64-darwin"],
+        "mainProgram": "hello",
+        "broken": false,
+        "homepage": "https://www.gnu.org/software/hello/manual/"
+    }]"#;
+
+    #[test]
+    fn nix_strings_are_escaped() {
+        assert_eq!(
+            nix_string(r#"[["a\"b","${x}"]]"#),
+            r#""[[\"a\\\"b\",\"\${x}\"]]""#
+        );
+    }
+
+    #[test]
+    fn enrich_attaches_meta() {
+        let mut info = FlakeInfo::from_stdout(SHOW.as_bytes()).unwrap();
+        let paths = r#"[["checks","x86_64-linux","broken"],["packages","x86_64-linux","hello"]]"#;
+        let backend = ReplayBackend::new().record(
+            [
+                "eval".to_string(),
+                "--json".to_string(),
+                "github:NixOS/nixpkgs#.".to_string(),
+                "--apply".to_string(),
+                META.replace("PATHS", &nix_string(paths)),
+            ],
+            NixOutput::success(META_JSON),
+        );
+
+        derivation_meta()
+            .url(FlakeRef::github("NixOS", "nixpkgs"))
+            .backend(backend)
+            .enrich(&mut info)
+            .unwrap();
+
+        let system = info.for_system("x86_64-linux");
+        assert_eq!(system.checks[0].meta, None);
+        let meta = system.packages[0].meta.as_ref().unwrap();
+        assert_eq!(meta.version.as_deref(), Some("2.12.1"));
+        assert_eq!(meta.license[0].spdx_id.as_deref(), Some("GPL-3.0-or-later"));
+        assert_eq!(meta.maintainers[0].github.as_deref(), Some("edolstra"));
+        assert_eq!(meta.main_program.as_deref(), Some("hello"));
+        assert!(!meta.broken);
+    }
+
+    #[test]
+    fn failed_batches_are_evaluated_one_by_one() {
+        let mut info = FlakeInfo::from_stdout(SHOW.as_bytes()).unwrap();
+        let eval = |paths: &str| {
+            [
+                "eval".to_string(),
+                "--json".to_string(),
+                ".#.".to_string(),
+                "--apply".to_string(),
+                META.replace("PATHS", &nix_string(paths)),
+            ]
+        };
+        let hello = META_JSON.trim_start_matches("[null,");
+        let backend = ReplayBackend::new()
+            .record(
+                eval(r#"[["checks","x86_64-linux","broken"],["packages","x86_64-linux","hello"]]"#),
+                NixOutput::failure(1, "error: attribute 'meta' missing"),
+            )
+            .record(
+                eval(r#"[["checks","x86_64-linux","broken"]]"#),
+                NixOutput::failure(1, "error: attribute 'meta' missing"),
+            )
+            .record(
+                eval(r#"[["packages","x86_64-linux","hello"]]"#),
+                NixOutput::success(format!("[{hello}")),
+            );
+
+        derivation_meta()
+            .backend(backend)
+            .enrich(&mut info)
+            .unwrap();
+
+        let system = info.for_system("x86_64-linux");
+        assert_eq!(system.checks[0].meta, None);
+        assert_eq!(
+            system.packages[0].meta.as_ref().unwrap().pname.as_deref(),
+            Some("hello")
+        );
+    }
+
+    #[test]
+    fn mismatched_output_is_an_error() {
+        let mut info = FlakeInfo::from_stdout(SHOW.as_bytes()).unwrap();
+        let backend = ReplayBackend::new().fallback(NixOutput::success("[null]"));
+
+        assert!(matches!(
+            derivation_meta().backend(backend).enrich(&mut info),
+            Err(FlakeShowError::MetaCountMismatch {
+                expected: 2,
+                got: 1
+            })
+        ));
+    }
+}
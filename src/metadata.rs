@@ -0,0 +1,170 @@
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::common::{common_setters, CommonArgs};
+use crate::{FlakeLock, FlakeRef, FlakeShowError, LockedRef};
+
+/// What `nix flake metadata --json` knows about a flake: where it came
+/// from, what it was resolved and locked to, and its lock file.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FlakeMetadata {
+    pub description: Option<String>,
+    /// the reference as given, e.g. `nixpkgs`.
+    pub original: LockedRef,
+    pub original_url: String,
+    /// the reference after registry lookup, e.g. `github:NixOS/nixpkgs/nixpkgs-unstable`.
+    pub resolved: LockedRef,
+    pub resolved_url: String,
+    /// the exact source that was fetched.
+    pub locked: LockedRef,
+    /// `locked` as a URL; `None` when it can't be locked, e.g. a dirty tree.
+    pub url: Option<String>,
+    /// the commit, for git-based flakes with a clean tree.
+    pub revision: Option<String>,
+    /// the commit with a `-dirty` suffix, for git trees with uncommitted changes.
+    pub dirty_revision: Option<String>,
+    pub rev_count: Option<u64>,
+    /// seconds since the epoch.
+    pub last_modified: Option<u64>,
+    /// where the source lives in the store.
+    pub path: PathBuf,
+    /// the lock file, as nix would write it for this flake.
+    pub locks: FlakeLock,
+}
+
+impl FlakeMetadata {
+    pub fn from_stdout(v: &[u8]) -> Result<Self, FlakeShowError> {
+        let metadata: FlakeMetadata =
+            serde_json::from_slice(v).map_err(|err| FlakeShowError::json(err, v))?;
+        Ok(FlakeMetadata {
+            locks: metadata.locks.check_version()?,
+            ..metadata
+        })
+    }
+
+    /// A reference that pins this exact revision of the flake.
+    pub fn locked_ref(&self) -> Result<FlakeRef, FlakeShowError> {
+        self.locked.to_flake_ref()
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct NixFlakeMetadataBuilder {
+    json: bool,
+    url: Option<FlakeRef>,
+    common: CommonArgs,
+}
+
+impl NixFlakeMetadataBuilder {
+    common_setters!();
+
+    pub fn json(mut self, json: bool) -> Self {
+        self.json = json;
+        self
+    }
+
+    pub fn url(mut self, url: impl Into<FlakeRef>) -> Self {
+        self.url = Some(url.into());
+        self
+    }
+
+    pub fn into_metadata(mut self) -> Result<FlakeMetadata, FlakeShowError> {
+        let runner = self.common.take_runner();
+        let stdout = runner.run(&self.json(true).build())?;
+        FlakeMetadata::from_stdout(&stdout)
+    }
+
+    pub fn build(self) -> std::process::Command {
+        let mut cmd = self.common.command(&["flake", "metadata"]);
+
+        if let Some(url) = self.url {
+            cmd.arg(url.to_string());
+        }
+
+        if self.json {
+            cmd.arg("--json");
+        }
+
+        self.common.push_flags(&mut cmd);
+        cmd
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{flake_metadata, NixOutput, ReplayBackend};
+
+    const METADATA: &str = r#"{
+        "description": "A collection of flake templates",
+        "lastModified": 1709643425,
+        "locked": {
+            "lastModified": 1709643425,
+            "narHash": "sha256-XyqpgqbN1tHJDgmi1i4aE3CNBc8EM+RDmmGo3vlQHEU=",
+            "owner": "NixOS",
+            "repo": "templates",
+            "rev": "2d6dcce2f3898090c8eda16a16abdff8a80e8ebf",
+            "type": "github"
+        },
+        "locks": {
+            "nodes": {"root": {}},
+            "root": "root",
+            "version": 7
+        },
+        "original": {"id": "templates", "type": "indirect"},
+        "originalUrl": "flake:templates",
+        "path": "/nix/store/zbrcvvhvq8r1d8mc6ddp0fqmmx4f5i0y-source",
+        "resolved": {"owner": "NixOS", "repo": "templates", "type": "github"},
+        "resolvedUrl": "github:NixOS/templates",
+        "revision": "2d6dcce2f3898090c8eda16a16abdff8a80e8ebf",
+        "url": "github:NixOS/templates/2d6dcce2f3898090c8eda16a16abdff8a80e8ebf"
+    }"#;
+
+    #[test]
+    fn parse_metadata() {
+        let metadata = FlakeMetadata::from_stdout(METADATA.as_bytes()).unwrap();
+
+        assert_eq!(
+            metadata.description.as_deref(),
+            Some("A collection of flake templates")
+        );
+        assert_eq!(metadata.original.id.as_deref(), Some("templates"));
+        assert_eq!(metadata.resolved_url, "github:NixOS/templates");
+        assert_eq!(metadata.last_modified, Some(1709643425));
+        assert_eq!(metadata.rev_count, None);
+        assert_eq!(metadata.locks.root, "root");
+        let locked = metadata.locked_ref().unwrap();
+        assert_eq!(locked.rev, metadata.revision);
+        assert_eq!(
+            locked.to_string().split('?').next(),
+            metadata.url.as_deref()
+        );
+
+        let future = METADATA.replace("\"version\": 7", "\"version\": 99");
+        assert!(matches!(
+            FlakeMetadata::from_stdout(future.as_bytes()),
+            Err(FlakeShowError::UnsupportedLockVersion(99))
+        ));
+    }
+
+    #[test]
+    fn replayed_metadata() {
+        let backend = ReplayBackend::new().record(
+            ["flake", "metadata", "templates", "--json", "--refresh"],
+            NixOutput::success(METADATA),
+        );
+
+        let metadata = flake_metadata()
+            .url(FlakeRef::indirect("templates"))
+            .refresh(true)
+            .backend(backend)
+            .into_metadata()
+            .unwrap();
+        assert_eq!(
+            metadata.path.to_str(),
+            Some("/nix/store/zbrcvvhvq8r1d8mc6ddp0fqmmx4f5i0y-source")
+        );
+    }
+}
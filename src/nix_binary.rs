@@ -0,0 +1,183 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Environment variable that, when set, names the nix binary to use.
+pub const NIX_BIN_ENV: &str = "NIX_BIN";
+
+/// A resolved `nix` executable that every spawned command goes through.
+///
+/// Resolution order is: an explicit override, the [`NIX_BIN_ENV`]
+/// environment variable, `$PATH`, and finally the well known profile
+/// locations (the default profile, NixOS system profile, per-user profiles).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NixBinary {
+    path: PathBuf,
+}
+
+impl NixBinary {
+    /// Use exactly this binary, without any discovery.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Discover nix from the environment, `$PATH` and well known locations.
+    pub fn discover() -> Option<Self> {
+        Self::resolve(None)
+    }
+
+    /// Like [`NixBinary::discover`], but an explicit path takes precedence.
+    pub fn resolve(explicit: Option<&Path>) -> Option<Self> {
+        resolve_from(
+            explicit,
+            std::env::var_os(NIX_BIN_ENV),
+            std::env::var_os("PATH"),
+            &well_known_locations(),
+        )
+    }
+
+    pub fn command(&self) -> std::process::Command {
+        std::process::Command::new(&self.path)
+    }
+}
+
+impl Default for NixBinary {
+    /// The discovered binary, resolved once per process so that every
+    /// command agrees on which nix it runs. Falls back to a bare `nix`.
+    fn default() -> Self {
+        static DISCOVERED: OnceLock<NixBinary> = OnceLock::new();
+        DISCOVERED
+            .get_or_init(|| NixBinary::discover().unwrap_or_else(|| NixBinary::new("nix")))
+            .clone()
+    }
+}
+
+fn resolve_from(
+    explicit: Option<&Path>,
+    env: Option<OsString>,
+    path_var: Option<OsString>,
+    well_known: &[PathBuf],
+) -> Option<NixBinary> {
+    if let Some(explicit) = explicit {
+        return Some(NixBinary::new(explicit));
+    }
+
+    if let Some(env) = env.filter(|env| !env.is_empty()) {
+        return Some(NixBinary::new(env));
+    }
+
+    if let Some(path_var) = path_var {
+        for dir in std::env::split_paths(&path_var) {
+            let candidate = dir.join("nix");
+            if is_executable(&candidate) {
+                return Some(NixBinary::new(candidate));
+            }
+        }
+    }
+
+    well_known
+        .iter()
+        .find(|candidate| is_executable(candidate))
+        .map(NixBinary::new)
+}
+
+fn well_known_locations() -> Vec<PathBuf> {
+    let mut locations = vec![
+        PathBuf::from("/nix/var/nix/profiles/default/bin/nix"),
+        PathBuf::from("/run/current-system/sw/bin/nix"),
+    ];
+
+    if let Some(home) = std::env::var_os("HOME") {
+        let home = PathBuf::from(home);
+        locations.push(home.join(".nix-profile/bin/nix"));
+        locations.push(home.join(".local/state/nix/profile/bin/nix"));
+    }
+
+    if let Some(user) = std::env::var_os("USER") {
+        locations.push(
+            Path::new("/etc/profiles/per-user")
+                .join(user)
+                .join("bin/nix"),
+        );
+    }
+
+    locations
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata()
+        .map(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::scratch_dir;
+
+    fn fake_nix(dir: &Path) -> PathBuf {
+        std::fs::create_dir_all(dir).unwrap();
+        let nix = dir.join("nix");
+        std::fs::write(&nix, "#!/bin/sh\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&nix, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        nix
+    }
+
+    #[test]
+    fn resolution_order() {
+        let root = scratch_dir("resolution-order");
+        let on_path = fake_nix(&root.join("path-bin"));
+        let well_known = fake_nix(&root.join("profile-bin"));
+        let path_var = Some(root.join("path-bin").into_os_string());
+
+        let explicit = resolve_from(
+            Some(Path::new("/explicit/nix")),
+            Some("/env/nix".into()),
+            path_var.clone(),
+            std::slice::from_ref(&well_known),
+        );
+        assert_eq!(explicit.unwrap().path(), Path::new("/explicit/nix"));
+
+        let env = resolve_from(None, Some("/env/nix".into()), path_var.clone(), &[]);
+        assert_eq!(env.unwrap().path(), Path::new("/env/nix"));
+
+        let from_path = resolve_from(
+            None,
+            Some("".into()),
+            path_var,
+            std::slice::from_ref(&well_known),
+        );
+        assert_eq!(from_path.unwrap().path(), on_path);
+
+        let from_profile = resolve_from(
+            None,
+            None,
+            None,
+            &[root.join("missing"), well_known.clone()],
+        );
+        assert_eq!(from_profile.unwrap().path(), well_known);
+
+        assert_eq!(
+            resolve_from(None, None, None, &[root.join("missing")]),
+            None
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
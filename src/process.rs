@@ -0,0 +1,302 @@
+use std::io::Read;
+use std::process::{Command, Output, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use bstr::ByteSlice;
+
+use crate::{FlakeShowError, StderrLineHandler};
+
+/// Run `cmd` to completion with stdout and stderr captured, returning stdout
+/// when nix exits successfully. `on_line` sees stderr while nix is running.
+///
+/// With a timeout, nix is started in its own process group so that the
+/// whole group (including builders and fetchers it spawned) can be killed
+/// once the timeout elapses.
+pub(crate) fn run_captured(
+    mut cmd: Command,
+    timeout: Option<Duration>,
+    on_line: Option<StderrLineHandler>,
+) -> Result<Vec<u8>, FlakeShowError> {
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+
+    if timeout.is_none() && on_line.is_none() {
+        return stdout_if_success(cmd.output().map_err(FlakeShowError::spawn)?);
+    }
+
+    if timeout.is_some() {
+        own_process_group(&mut cmd);
+    }
+    let mut child = cmd.spawn().map_err(FlakeShowError::spawn)?;
+
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let stdout_reader = std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        stdout.read_to_end(&mut buf).map(|_| buf)
+    });
+
+    // stderr is shared rather than returned from the thread, so that whatever
+    // was printed before a timeout can still be reported.
+    let stderr = Arc::new(Mutex::new(Vec::new()));
+    let mut stderr_pipe = child.stderr.take().expect("stderr is piped");
+    let stderr_reader = std::thread::spawn({
+        let stderr = Arc::clone(&stderr);
+        let mut lines = LineSplitter::new(on_line);
+        move || {
+            let mut chunk = [0; 4096];
+            loop {
+                match stderr_pipe.read(&mut chunk) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        stderr.lock().unwrap().extend_from_slice(&chunk[..n]);
+                        lines.feed(&chunk[..n]);
+                    }
+                }
+            }
+            lines.finish();
+        }
+    });
+
+    let status = match timeout {
+        None => child.wait().map_err(FlakeShowError::Spawn)?,
+        Some(timeout) => {
+            let deadline = Instant::now() + timeout;
+            loop {
+                if let Some(status) = child.try_wait().map_err(FlakeShowError::Spawn)? {
+                    break status;
+                }
+
+                let now = Instant::now();
+                if now >= deadline {
+                    kill_process_group(child.id());
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    let stderr = stderr.lock().unwrap().to_str_lossy().into_owned();
+                    return Err(FlakeShowError::Timeout { timeout, stderr });
+                }
+                std::thread::sleep((deadline - now).min(Duration::from_millis(20)));
+            }
+        }
+    };
+
+    let stdout = stdout_reader
+        .join()
+        .expect("stdout reader panicked")
+        .map_err(FlakeShowError::Spawn)?;
+    stderr_reader.join().expect("stderr reader panicked");
+    let stderr = std::mem::take(&mut *stderr.lock().unwrap());
+
+    stdout_if_success(Output {
+        status,
+        stdout,
+        stderr,
+    })
+}
+
+/// The async counterpart of [`run_captured`]. nix is killed if the returned
+/// future is dropped before it completes.
+#[cfg(feature = "tokio")]
+pub(crate) async fn run_captured_async(
+    mut cmd: Command,
+    timeout: Option<Duration>,
+    on_line: Option<StderrLineHandler>,
+) -> Result<Vec<u8>, FlakeShowError> {
+    use tokio::io::AsyncReadExt;
+
+    if timeout.is_some() {
+        own_process_group(&mut cmd);
+    }
+
+    let mut cmd = tokio::process::Command::from(cmd);
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+    cmd.kill_on_drop(true);
+
+    if timeout.is_none() && on_line.is_none() {
+        return stdout_if_success(cmd.output().await.map_err(FlakeShowError::spawn)?);
+    }
+
+    let mut child = cmd.spawn().map_err(FlakeShowError::spawn)?;
+    let pid = child.id();
+    let mut stdout_pipe = child.stdout.take().expect("stdout is piped");
+    let mut stderr_pipe = child.stderr.take().expect("stderr is piped");
+    let mut stderr = Vec::new();
+    let mut lines = LineSplitter::new(on_line);
+
+    let run = async {
+        let mut stdout = Vec::new();
+        let read_stderr = async {
+            let mut chunk = [0; 4096];
+            loop {
+                match stderr_pipe.read(&mut chunk).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        stderr.extend_from_slice(&chunk[..n]);
+                        lines.feed(&chunk[..n]);
+                    }
+                }
+            }
+            lines.finish();
+        };
+        let (status, read_stdout, ()) = tokio::join!(
+            child.wait(),
+            stdout_pipe.read_to_end(&mut stdout),
+            read_stderr
+        );
+        read_stdout.map_err(FlakeShowError::Spawn)?;
+        Ok::<_, FlakeShowError>((status.map_err(FlakeShowError::Spawn)?, stdout))
+    };
+
+    let result = match timeout {
+        None => run.await,
+        Some(timeout) => match tokio::time::timeout(timeout, run).await {
+            Ok(result) => result,
+            Err(_) => {
+                if let Some(pid) = pid {
+                    kill_process_group(pid);
+                }
+                let _ = child.kill().await;
+                return Err(FlakeShowError::Timeout {
+                    timeout,
+                    stderr: stderr.to_str_lossy().into_owned(),
+                });
+            }
+        },
+    };
+
+    let (status, stdout) = result?;
+    stdout_if_success(Output {
+        status,
+        stdout,
+        stderr,
+    })
+}
+
+/// Reassembles stderr chunks into lines for a [`StderrLineHandler`].
+struct LineSplitter {
+    pending: Vec<u8>,
+    on_line: Option<StderrLineHandler>,
+}
+
+impl LineSplitter {
+    fn new(on_line: Option<StderrLineHandler>) -> Self {
+        Self {
+            pending: Vec::new(),
+            on_line,
+        }
+    }
+
+    fn feed(&mut self, chunk: &[u8]) {
+        let Some(on_line) = &mut self.on_line else {
+            return;
+        };
+        self.pending.extend_from_slice(chunk);
+        while let Some(end) = self.pending.find_byte(b'\n') {
+            on_line(&self.pending[..end]);
+            self.pending.drain(..=end);
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(on_line) = &mut self.on_line {
+            if !self.pending.is_empty() {
+                on_line(&self.pending);
+                self.pending.clear();
+            }
+        }
+    }
+}
+
+fn stdout_if_success(output: Output) -> Result<Vec<u8>, FlakeShowError> {
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        Err(FlakeShowError::NonZeroExit {
+            code: output.status.code(),
+            stderr: output.stderr.to_str_lossy().into_owned(),
+        })
+    }
+}
+
+#[cfg(unix)]
+fn own_process_group(cmd: &mut Command) {
+    use std::os::unix::process::CommandExt;
+    cmd.process_group(0);
+}
+
+#[cfg(not(unix))]
+fn own_process_group(_cmd: &mut Command) {}
+
+#[cfg(unix)]
+fn kill_process_group(pid: u32) {
+    // the child leads its own group, so its pid is also the group id.
+    unsafe {
+        libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+#[cfg(not(unix))]
+fn kill_process_group(_pid: u32) {}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeout_kills_and_keeps_stderr() {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "echo evaluating >&2; sleep 30"]);
+
+        let started = Instant::now();
+        let err = run_captured(cmd, Some(Duration::from_millis(300)), None).unwrap_err();
+
+        assert!(started.elapsed() < Duration::from_secs(10));
+        match err {
+            FlakeShowError::Timeout { stderr, .. } => assert_eq!(stderr.trim(), "evaluating"),
+            other => panic!("unexpected error {other:?}"),
+        }
+    }
+
+    #[test]
+    fn finishes_within_timeout() {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "echo out; echo err >&2; exit 3"]);
+
+        match run_captured(cmd, Some(Duration::from_secs(30)), None).unwrap_err() {
+            FlakeShowError::NonZeroExit { code, stderr } => {
+                assert_eq!(code, Some(3));
+                assert_eq!(stderr.trim(), "err");
+            }
+            other => panic!("unexpected error {other:?}"),
+        }
+    }
+
+    #[test]
+    fn stderr_lines_are_streamed() {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "printf 'one\\ntwo\\nthree' >&2"]);
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let on_line: StderrLineHandler = Box::new(move |line| tx.send(line.to_vec()).unwrap());
+        run_captured(cmd, None, Some(on_line)).unwrap();
+
+        let lines: Vec<_> = rx.iter().collect();
+        assert_eq!(lines, [&b"one"[..], b"two", b"three"]);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn async_timeout_kills_and_keeps_stderr() {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "echo evaluating >&2; sleep 30"]);
+
+        let err = run_captured_async(cmd, Some(Duration::from_millis(300)), None)
+            .await
+            .unwrap_err();
+        match err {
+            FlakeShowError::Timeout { stderr, .. } => assert_eq!(stderr.trim(), "evaluating"),
+            other => panic!("unexpected error {other:?}"),
+        }
+    }
+}
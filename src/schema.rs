@@ -0,0 +1,138 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{FlakeInfo, FlakeShowError};
+
+/// The version of the JSON written by [`FlakeInfo::to_json`]. It goes up
+/// whenever a field is renamed, removed or changes meaning.
+pub const FLAKE_INFO_SCHEMA_VERSION: u32 = 1;
+
+/// A [`FlakeInfo`] with the schema version it was written with, next to
+/// its fields.
+#[derive(Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(
+    title = "FlakeInfo",
+    rename = "FlakeInfoDocument",
+    description = "A flake's outputs, with the version of this schema they were written with."
+)]
+struct Document<T> {
+    #[schemars(extend("const" = FLAKE_INFO_SCHEMA_VERSION))]
+    schema_version: u32,
+    #[serde(flatten)]
+    flake: T,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Version {
+    schema_version: u32,
+}
+
+impl FlakeInfo {
+    /// This flake info in this crate's own JSON schema, which is stable
+    /// across nix versions, unlike the output of `nix flake show --json`.
+    /// See [`FlakeInfo::json_schema`].
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(Document {
+            schema_version: FLAKE_INFO_SCHEMA_VERSION,
+            flake: self,
+        })
+        .expect("flake infos always serialize")
+    }
+
+    /// Read back what [`FlakeInfo::to_json`] wrote, failing for other
+    /// schema versions.
+    pub fn from_json(v: &[u8]) -> Result<Self, FlakeShowError> {
+        let version: Version =
+            serde_json::from_slice(v).map_err(|err| FlakeShowError::json(err, v))?;
+        if version.schema_version != FLAKE_INFO_SCHEMA_VERSION {
+            return Err(FlakeShowError::UnsupportedSchemaVersion(
+                version.schema_version,
+            ));
+        }
+        serde_json::from_slice::<Document<FlakeInfo>>(v)
+            .map(|document| document.flake)
+            .map_err(|err| FlakeShowError::json(err, v))
+    }
+
+    /// The JSON Schema of [`FlakeInfo::to_json`], for consumers that don't
+    /// use this crate. A copy is kept in `schema/flake-info.schema.json`.
+    pub fn json_schema() -> serde_json::Value {
+        serde_json::to_value(schemars::schema_for!(Document<FlakeInfo>))
+            .expect("schemas always serialize")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DerivationMeta;
+
+    const SCHEMA_FILE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/schema/flake-info.schema.json");
+
+    #[test]
+    fn round_trips() {
+        let mut info = FlakeInfo::from_stdout(
+            br#"{
+                "apps": {"x86_64-linux": {"default": {"type": "app"}}},
+                "packages": {"x86_64-linux": {"hello": {"name": "hello-2.12.1", "type": "derivation"}}},
+                "legacyPackages": {"aarch64-darwin": {"type": "omitted"}},
+                "overlays": {"default": {"type": "nixpkgs-overlay"}},
+                "templates": {"rust": {"description": "A Rust crate"}},
+                "deploy": {"nodes": {"box": {"type": "unknown"}}}
+            }"#,
+        )
+        .unwrap();
+        info.packages.get_mut("x86_64-linux").unwrap()[0].meta = Some(DerivationMeta {
+            main_program: Some("hello".into()),
+            ..DerivationMeta::default()
+        });
+
+        let json = info.to_json();
+        assert_eq!(json["schemaVersion"], FLAKE_INFO_SCHEMA_VERSION);
+        assert_eq!(
+            json["packages"]["x86_64-linux"][0]["attrPath"],
+            serde_json::json!(["packages", "x86_64-linux", "hello"])
+        );
+        assert_eq!(
+            json["packages"]["x86_64-linux"][0]["meta"]["mainProgram"],
+            "hello"
+        );
+        assert_eq!(
+            json["otherOutputs"]["deploy"],
+            serde_json::json!({
+                "node": "attrs",
+                "children": {"nodes": {"node": "attrs", "children": {"box": {"node": "unknown"}}}}
+            })
+        );
+
+        let read = FlakeInfo::from_json(json.to_string().as_bytes()).unwrap();
+        assert_eq!(read, info);
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        assert!(matches!(
+            FlakeInfo::from_json(br#"{"schemaVersion": 2}"#),
+            Err(FlakeShowError::UnsupportedSchemaVersion(2))
+        ));
+    }
+
+    /// Run with `UPDATE_SCHEMA=1` after changing any of the types.
+    #[test]
+    fn schema_file_is_up_to_date() {
+        let schema = serde_json::to_string_pretty(&FlakeInfo::json_schema()).unwrap() + "\n";
+        if std::env::var_os("UPDATE_SCHEMA").is_some() {
+            std::fs::write(SCHEMA_FILE, &schema).unwrap();
+        }
+        assert_eq!(
+            std::fs::read_to_string(SCHEMA_FILE).unwrap(),
+            schema,
+            "schema/flake-info.schema.json is out of date, rerun with UPDATE_SCHEMA=1"
+        );
+        // descriptions come from doc comments, which mustn't leak rustdoc
+        // links or Rust values
+        assert!(!schema.contains("[`"));
+        assert!(!schema.contains("`None`"));
+    }
+}
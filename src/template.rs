@@ -0,0 +1,140 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::process::Command;
+
+use serde::Deserialize;
+
+use crate::common::{common_setters, CommonArgs};
+use crate::{FlakeRef, FlakeShowError, Installable};
+
+/// Reduces every template to what [`Template`] holds, listing the files of
+/// its directory recursively, relative to the directory.
+const TEMPLATES: &str = "templates: let \
+    files = dir: prefix: builtins.concatLists (builtins.attrValues (builtins.mapAttrs (name: type: \
+        if type == \"directory\" then files (dir + \"/${name}\") \"${prefix}${name}/\" \
+        else [ \"${prefix}${name}\" ]) (builtins.readDir dir))); \
+    in builtins.mapAttrs (name: template: { \
+        description = template.description or \"\"; \
+        path = template.path; \
+        welcomeText = template.welcomeText or null; \
+        files = files template.path \"\"; \
+    }) templates";
+
+/// A template of a flake with everything `nix flake show` leaves out, see
+/// [`NixFlakeTemplatesBuilder::into_templates`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Template {
+    pub description: String,
+    /// the template directory, copied to the store.
+    pub path: PathBuf,
+    /// shown by `nix flake init` after copying the template, as markdown.
+    pub welcome_text: Option<String>,
+    /// every file of the template, relative to `path`, in sorted order.
+    pub files: Vec<PathBuf>,
+}
+
+impl Template {
+    /// Parse the output of evaluating [`TEMPLATES`], from template name to
+    /// template.
+    pub fn from_stdout(v: &[u8]) -> Result<BTreeMap<String, Self>, FlakeShowError> {
+        let mut templates: BTreeMap<String, Template> =
+            serde_json::from_slice(v).map_err(|err| FlakeShowError::json(err, v))?;
+        for template in templates.values_mut() {
+            template.files.sort();
+        }
+        Ok(templates)
+    }
+}
+
+/// `nix eval` of a flake's `templates` output, for the parts of
+/// [`FlakeInfo::templates`](crate::FlakeInfo::templates) that `nix flake
+/// show` doesn't print.
+#[derive(Default, Debug)]
+pub struct NixFlakeTemplatesBuilder {
+    url: Option<FlakeRef>,
+    common: CommonArgs,
+}
+
+impl NixFlakeTemplatesBuilder {
+    common_setters!();
+
+    /// The flake to read templates from, the one in the current directory
+    /// by default.
+    pub fn url(mut self, url: impl Into<FlakeRef>) -> Self {
+        self.url = Some(url.into());
+        self
+    }
+
+    /// Every template of the flake, from name to template.
+    pub fn into_templates(mut self) -> Result<BTreeMap<String, Template>, FlakeShowError> {
+        let runner = self.common.take_runner();
+        let stdout = runner.run(&self.build())?;
+        Template::from_stdout(&stdout)
+    }
+
+    pub fn build(self) -> Command {
+        let mut cmd = self.common.command(&["eval", "--json"]);
+
+        let flake = self.url.clone().unwrap_or_else(|| FlakeRef::path("."));
+        cmd.arg(Installable::flake_attr(flake, &["templates"]).as_str())
+            .arg("--apply")
+            .arg(TEMPLATES);
+
+        self.common.push_flags(&mut cmd);
+        cmd
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{flake_templates, NixOutput, ReplayBackend};
+
+    const TEMPLATES_JSON: &str = r##"{
+        "rust": {
+            "description": "Rust template, using Naersk",
+            "path": "/nix/store/t1-source/rust",
+            "welcomeText": "# Rust\n\nRun `nix build`.",
+            "files": ["src/main.rs", "Cargo.toml", "flake.nix"]
+        },
+        "trivial": {
+            "description": "A very basic flake",
+            "path": "/nix/store/t1-source/trivial",
+            "welcomeText": null,
+            "files": ["flake.nix"]
+        }
+    }"##;
+
+    #[test]
+    fn replayed_templates() {
+        let backend = ReplayBackend::new().record(
+            [
+                "eval",
+                "--json",
+                "templates#templates",
+                "--apply",
+                TEMPLATES,
+            ],
+            NixOutput::success(TEMPLATES_JSON),
+        );
+
+        let templates = flake_templates()
+            .url(FlakeRef::indirect("templates"))
+            .backend(backend)
+            .into_templates()
+            .unwrap();
+
+        let rust = &templates["rust"];
+        assert_eq!(rust.path, PathBuf::from("/nix/store/t1-source/rust"));
+        assert_eq!(
+            rust.welcome_text.as_deref(),
+            Some("# Rust\n\nRun `nix build`.")
+        );
+        assert_eq!(
+            rust.files,
+            ["Cargo.toml", "flake.nix", "src/main.rs"].map(PathBuf::from)
+        );
+        assert_eq!(templates["trivial"].welcome_text, None);
+    }
+}
@@ -0,0 +1,12 @@
+//! Helpers shared by the unit tests.
+
+use std::path::PathBuf;
+
+/// A fresh, empty directory in the system's temp dir, unique to `name` and
+/// this test run. Tests remove it themselves when they're done.
+pub(crate) fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("nix-flake-show-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
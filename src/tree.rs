@@ -0,0 +1,484 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::{Derivation, FlakeInfo, OutputNode};
+
+const BOLD: &str = "\x1b[1m";
+const WARNING: &str = "\x1b[35;1m";
+const NORMAL: &str = "\x1b[0m";
+
+/// Branch, last branch, continued line and blank, as `nix flake show`
+/// draws them.
+const UNICODE: [&str; 4] = ["├───", "└───", "│   ", "    "];
+const ASCII: [&str; 4] = ["|---", "`---", "|   ", "    "];
+
+/// Outputs laid out as `<output>.<system>.<attr>`, which collapse to a
+/// single line for systems that aren't shown.
+const PER_SYSTEM: &[&str] = &[
+    "apps",
+    "checks",
+    "devShells",
+    "formatter",
+    "legacyPackages",
+    "packages",
+];
+
+/// Renders a [`FlakeInfo`] as the tree `nix flake show` prints, through
+/// its [`fmt::Display`] implementation.
+///
+/// ```
+/// # use nix_flake_show::FlakeInfo;
+/// let info = FlakeInfo::from_stdout(
+///     br#"{"packages": {"x86_64-linux": {"hello": {"name": "hello-2.12", "type": "derivation"}}}}"#,
+/// )?;
+/// assert_eq!(
+///     info.tree().header("github:me/hello").to_string(),
+///     "github:me/hello\n\
+///      └───packages\n    \
+///          └───x86_64-linux\n        \
+///              └───hello: package 'hello-2.12'\n"
+/// );
+/// # Ok::<_, nix_flake_show::FlakeShowError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct TreeRenderer<'a> {
+    info: &'a FlakeInfo,
+    header: Option<String>,
+    width: Option<usize>,
+    unicode: bool,
+    color: bool,
+    systems: Vec<String>,
+}
+
+impl FlakeInfo {
+    pub fn tree(&self) -> TreeRenderer<'_> {
+        TreeRenderer::new(self)
+    }
+}
+
+/// What is printed after an attribute.
+enum Leaf {
+    /// after a colon, e.g. `package 'hello-2.12'`.
+    Text(String),
+    /// skipped by nix, with the flag that would show it.
+    Omitted(&'static str),
+}
+
+#[derive(Default)]
+struct Node {
+    leaf: Option<Leaf>,
+    children: BTreeMap<String, Node>,
+}
+
+impl Node {
+    fn child(&mut self, attr: &str) -> &mut Node {
+        self.children.entry(attr.to_string()).or_default()
+    }
+
+    fn insert(&mut self, path: &[String], leaf: Leaf) {
+        let node = path.iter().fold(self, |node, attr| node.child(attr));
+        node.leaf = Some(leaf);
+    }
+
+    fn omit(&mut self, flag: &'static str) {
+        self.children.clear();
+        self.leaf = Some(Leaf::Omitted(flag));
+    }
+}
+
+impl<'a> TreeRenderer<'a> {
+    /// Unicode box drawing without colors, every system expanded.
+    pub fn new(info: &'a FlakeInfo) -> Self {
+        TreeRenderer {
+            info,
+            header: None,
+            width: None,
+            unicode: true,
+            color: false,
+            systems: Vec::new(),
+        }
+    }
+
+    /// The first line, where nix prints the locked flake reference.
+    pub fn header(mut self, header: impl fmt::Display) -> Self {
+        self.header = Some(header.to_string());
+        self
+    }
+
+    /// Cut lines off after `width` characters, as nix does for a terminal.
+    pub fn width(mut self, width: usize) -> Self {
+        self.width = Some(width);
+        self
+    }
+
+    /// Draw the tree with `|---` instead of `├───`.
+    pub fn unicode(mut self, unicode: bool) -> Self {
+        self.unicode = unicode;
+        self
+    }
+
+    /// Highlight attributes and warnings with ANSI escapes.
+    pub fn color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Only expand `system`, collapsing the others into an "omitted" line,
+    /// the way nix does without `--all-systems`. Can be called more than
+    /// once.
+    pub fn system(mut self, system: impl Into<String>) -> Self {
+        self.systems.push(system.into());
+        self
+    }
+
+    fn bold(&self, text: &str) -> String {
+        self.styled(BOLD, text)
+    }
+
+    fn warning(&self, text: &str) -> String {
+        self.styled(WARNING, text)
+    }
+
+    fn styled(&self, style: &str, text: &str) -> String {
+        if self.color {
+            format!("{style}{text}{NORMAL}")
+        } else {
+            text.to_string()
+        }
+    }
+
+    fn derivation(&self, output: &str, deriv: &Derivation) -> String {
+        let what = match output {
+            "devShells" => "development environment",
+            "checks" | "hydraJobs" => "derivation",
+            _ => "package",
+        };
+        self.described(
+            format!("{what} '{}'", deriv.name),
+            deriv.description.as_deref(),
+        )
+    }
+
+    fn described(&self, label: String, description: Option<&str>) -> String {
+        match description {
+            Some(description) => format!("{label} - '{description}'"),
+            None => label,
+        }
+    }
+
+    fn app(&self, description: Option<&str>) -> String {
+        match description {
+            Some(description) => format!("app: {}", self.bold(description)),
+            None => "app".to_string(),
+        }
+    }
+
+    /// How nix describes a node of type `kind`.
+    fn kind(&self, kind: &str, name: Option<&str>, description: Option<&str>) -> String {
+        match (kind, name) {
+            ("derivation", Some(name)) => self.described(format!("package '{name}'"), description),
+            ("app", _) => self.app(description),
+            ("nixos-module", _) => "NixOS module".to_string(),
+            ("nixpkgs-overlay", _) => "Nixpkgs overlay".to_string(),
+            ("nixos-configuration", _) => "NixOS configuration".to_string(),
+            ("template", _) => format!("template: {}", self.bold(description.unwrap_or(""))),
+            _ => self.warning("unknown"),
+        }
+    }
+
+    fn other_output(&self, node: &OutputNode, into: &mut Node) {
+        match node {
+            OutputNode::Leaf {
+                kind,
+                name,
+                description,
+            } => {
+                into.leaf = Some(Leaf::Text(self.kind(
+                    kind,
+                    name.as_deref(),
+                    description.as_deref(),
+                )))
+            }
+            OutputNode::Attrs(children) => {
+                for (attr, child) in children {
+                    self.other_output(child, into.child(attr));
+                }
+            }
+            OutputNode::Unknown => into.leaf = Some(Leaf::Text(self.warning("unknown"))),
+            OutputNode::Omitted => into.omit("--all-systems"),
+        }
+    }
+
+    fn tree(&self) -> Node {
+        let info = self.info;
+        let mut root = Node::default();
+
+        for (system, apps) in &info.apps {
+            let node = root.child("apps").child(system);
+            for app in apps {
+                node.child(&app.invocation).leaf =
+                    Some(Leaf::Text(self.app(app.description.as_deref())));
+            }
+        }
+        for (output, by_system) in [
+            ("checks", &info.checks),
+            ("devShells", &info.dev_shells),
+            ("packages", &info.packages),
+            ("legacyPackages", &info.legacy_packages),
+        ] {
+            for (system, derivs) in by_system {
+                let node = root.child(output).child(system);
+                for deriv in derivs {
+                    let text = self.derivation(output, deriv);
+                    // hand built derivations may not have a full path
+                    let path = deriv
+                        .attr_path
+                        .get(2..)
+                        .unwrap_or(std::slice::from_ref(&deriv.invocation));
+                    node.insert(path, Leaf::Text(text));
+                }
+            }
+        }
+        for (system, deriv) in &info.formatter {
+            root.child("formatter").child(system).leaf =
+                Some(Leaf::Text(self.derivation("formatter", deriv)));
+        }
+        for job in &info.hydra_jobs {
+            let text = self.derivation("hydraJobs", &job.derivation);
+            root.child("hydraJobs").insert(&job.path, Leaf::Text(text));
+        }
+
+        for overlay in &info.overlays {
+            root.child("overlays").child(&overlay.name).leaf =
+                Some(Leaf::Text(self.kind(&overlay.kind, None, None)));
+        }
+        for module in &info.nixos_modules {
+            root.child("nixosModules").child(&module.name).leaf =
+                Some(Leaf::Text(self.kind(&module.kind, None, None)));
+        }
+        for (output, configs) in [
+            ("nixosConfigurations", &info.nixos_configurations),
+            ("darwinConfigurations", &info.darwin_configurations),
+            ("homeConfigurations", &info.home_configurations),
+        ] {
+            for config in configs {
+                root.child(output).child(&config.name).leaf =
+                    Some(Leaf::Text(self.kind(&config.kind, None, None)));
+            }
+        }
+        if let Some(lib) = &info.lib {
+            self.other_output(lib, root.child("lib"));
+        }
+        for (name, description) in &info.templates {
+            root.child("templates").child(name).leaf =
+                Some(Leaf::Text(self.kind("template", None, Some(description))));
+        }
+        for (output, node) in &info.other_outputs {
+            self.other_output(node, root.child(output));
+        }
+
+        for path in &info.omitted {
+            let flag = match path.first().map(String::as_str) {
+                Some("legacyPackages") => "--legacy",
+                _ => "--all-systems",
+            };
+            path.iter()
+                .fold(&mut root, |node, attr| node.child(attr))
+                .omit(flag);
+        }
+        if !self.systems.is_empty() {
+            for output in PER_SYSTEM {
+                let Some(node) = root.children.get_mut(*output) else {
+                    continue;
+                };
+                for (system, node) in &mut node.children {
+                    if !self.systems.contains(system)
+                        && !matches!(node.leaf, Some(Leaf::Omitted(_)))
+                    {
+                        node.omit("--all-systems");
+                    }
+                }
+            }
+        }
+        root
+    }
+
+    fn write_children(&self, f: &mut fmt::Formatter<'_>, node: &Node, prefix: &str) -> fmt::Result {
+        let [branch, last, line, blank] = if self.unicode { UNICODE } else { ASCII };
+        let count = node.children.len();
+        for (i, (attr, child)) in node.children.iter().enumerate() {
+            let is_last = i + 1 == count;
+            let mut text = format!(
+                "{prefix}{}{}",
+                if is_last { last } else { branch },
+                self.bold(attr)
+            );
+            match &child.leaf {
+                Some(Leaf::Text(leaf)) => {
+                    text.push_str(": ");
+                    text.push_str(leaf);
+                }
+                Some(Leaf::Omitted(flag)) => {
+                    text.push_str(&format!(
+                        " {} (use '{flag}' to show)",
+                        self.warning("omitted")
+                    ));
+                }
+                None => {}
+            }
+            self.write_line(f, &text)?;
+
+            let prefix = format!("{prefix}{}", if is_last { blank } else { line });
+            self.write_children(f, child, &prefix)?;
+        }
+        Ok(())
+    }
+
+    fn write_line(&self, f: &mut fmt::Formatter<'_>, line: &str) -> fmt::Result {
+        match self.width {
+            Some(width) => f.write_str(&truncate(line, width))?,
+            None => f.write_str(line)?,
+        }
+        f.write_str("\n")
+    }
+}
+
+impl fmt::Display for TreeRenderer<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(header) = &self.header {
+            self.write_line(f, &self.bold(header))?;
+        }
+        self.write_children(f, &self.tree(), "")
+    }
+}
+
+/// `line` cut off after `width` visible characters, keeping every ANSI
+/// escape so colors are still reset.
+fn truncate(line: &str, width: usize) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut visible = 0;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            out.push(c);
+            for c in chars.by_ref() {
+                out.push(c);
+                if c != '[' && ('@'..='~').contains(&c) {
+                    break;
+                }
+            }
+        } else if visible < width {
+            out.push(c);
+            visible += 1;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHOW: &str = r#"{
+        "devShells": {
+            "aarch64-darwin": {"type": "omitted"},
+            "x86_64-linux": {"default": {"name": "nix-shell", "type": "derivation"}}
+        },
+        "formatter": {"x86_64-linux": {"name": "alejandra-3.0.0", "type": "derivation"}},
+        "legacyPackages": {"x86_64-linux": {"type": "omitted"}},
+        "overlays": {"default": {"type": "nixpkgs-overlay"}},
+        "packages": {
+            "aarch64-darwin": {"hello": {"name": "hello-2.12.1", "type": "derivation"}},
+            "x86_64-linux": {
+                "hello": {"name": "hello-2.12.1", "type": "derivation", "description": "Says hello"}
+            }
+        },
+        "templates": {"rust": {"description": "A Rust crate"}}
+    }"#;
+
+    fn info() -> FlakeInfo {
+        FlakeInfo::from_stdout(SHOW.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn matches_nix() {
+        assert_eq!(
+            info().tree().header("path:/src/app").to_string(),
+            "\
+path:/src/app
+├───devShells
+│   ├───aarch64-darwin omitted (use '--all-systems' to show)
+│   └───x86_64-linux
+│       └───default: development environment 'nix-shell'
+├───formatter
+│   └───x86_64-linux: package 'alejandra-3.0.0'
+├───legacyPackages
+│   └───x86_64-linux omitted (use '--legacy' to show)
+├───overlays
+│   └───default: Nixpkgs overlay
+├───packages
+│   ├───aarch64-darwin
+│   │   └───hello: package 'hello-2.12.1'
+│   └───x86_64-linux
+│       └───hello: package 'hello-2.12.1' - 'Says hello'
+└───templates
+    └───rust: template: A Rust crate
+"
+        );
+    }
+
+    #[test]
+    fn collapsed_ascii_and_narrow() {
+        let info = info();
+        let tree = info
+            .tree()
+            .unicode(false)
+            .system("x86_64-linux")
+            .width(30)
+            .to_string();
+        let packages: Vec<_> = tree.lines().skip(10).take(4).collect();
+        assert_eq!(
+            packages,
+            [
+                "|---packages",
+                "|   |---aarch64-darwin omitted",
+                "|   `---x86_64-linux",
+                "|       `---hello: package 'he",
+            ]
+        );
+    }
+
+    #[test]
+    fn short_attr_paths() {
+        let mut info = FlakeInfo::from_stdout(
+            br#"{"packages": {"x86_64-linux": {"hello": {"name": "hello-2.12.1", "type": "derivation"}}}}"#,
+        )
+        .unwrap();
+        // e.g. read back from JSON that left it out
+        info.packages.get_mut("x86_64-linux").unwrap()[0]
+            .attr_path
+            .clear();
+        assert_eq!(
+            info.tree().to_string(),
+            "\
+└───packages
+    └───x86_64-linux
+        └───hello: package 'hello-2.12.1'
+"
+        );
+    }
+
+    #[test]
+    fn colors() {
+        let info = info();
+        let tree = info.tree().color(true).to_string();
+        assert!(tree.starts_with("├───\x1b[1mdevShells\x1b[0m\n"));
+        assert!(tree.contains(
+            "├───\x1b[1maarch64-darwin\x1b[0m \x1b[35;1momitted\x1b[0m (use '--all-systems' to show)\n"
+        ));
+        assert_eq!(
+            truncate("├───\x1b[1maarch64-darwin\x1b[0m", 8),
+            "├───\x1b[1maarc\x1b[0m"
+        );
+    }
+}
@@ -0,0 +1,286 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::common::{common_setters, CommonArgs};
+use crate::{FlakeLock, FlakeRef, FlakeShowError, LockDiff, LockNode};
+
+/// The lock file of the flake in `dir`, `None` if it doesn't have one yet.
+fn read_lock(dir: &Path) -> Result<Option<FlakeLock>, FlakeShowError> {
+    match FlakeLock::read(dir.join("flake.lock")) {
+        Ok(lock) => Ok(Some(lock)),
+        Err(FlakeShowError::Io(err)) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// A flake without a lock file is as good as one without inputs.
+fn lock_diff(before: Option<FlakeLock>, after: Option<FlakeLock>) -> LockDiff {
+    let empty = || FlakeLock {
+        version: 7,
+        root: "root".to_string(),
+        nodes: [(
+            "root".to_string(),
+            LockNode {
+                inputs: BTreeMap::new(),
+                locked: None,
+                original: None,
+                flake: true,
+            },
+        )]
+        .into(),
+    };
+    before
+        .unwrap_or_else(empty)
+        .diff(&after.unwrap_or_else(empty))
+}
+
+/// `nix flake update`, which re-locks all inputs of a local flake, or only
+/// the ones given.
+#[derive(Default, Debug)]
+pub struct NixFlakeUpdateBuilder {
+    inputs: Vec<String>,
+    flake: Option<PathBuf>,
+    common: CommonArgs,
+}
+
+impl NixFlakeUpdateBuilder {
+    common_setters!();
+
+    /// Only update `input`, e.g. `nixpkgs` or `home-manager/nixpkgs`.
+    pub fn input(mut self, input: impl Into<String>) -> Self {
+        self.inputs.push(input.into());
+        self
+    }
+
+    /// The directory of the flake to update, the current one by default.
+    pub fn flake(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.flake = Some(dir.into());
+        self
+    }
+
+    /// Update the lock file, returning what changed in it.
+    pub fn into_diff(mut self) -> Result<LockDiff, FlakeShowError> {
+        let dir = self.flake.clone().unwrap_or_else(|| PathBuf::from("."));
+        let runner = self.common.take_runner();
+
+        let before = read_lock(&dir)?;
+        runner.run(&self.build())?;
+        Ok(lock_diff(before, read_lock(&dir)?))
+    }
+
+    pub fn build(self) -> Command {
+        let mut cmd = self.common.command(&["flake", "update"]);
+
+        cmd.args(&self.inputs);
+
+        if let Some(flake) = &self.flake {
+            cmd.arg("--flake").arg(FlakeRef::path(flake).to_string());
+        }
+
+        self.common.push_flags(&mut cmd);
+        cmd
+    }
+}
+
+/// `nix flake lock`, which locks inputs missing from the lock file, and can
+/// update or override individual ones.
+#[derive(Default, Debug)]
+pub struct NixFlakeLockBuilder {
+    flake: Option<PathBuf>,
+    update_inputs: Vec<String>,
+    override_inputs: Vec<(String, FlakeRef)>,
+    common: CommonArgs,
+}
+
+impl NixFlakeLockBuilder {
+    common_setters!();
+
+    /// The directory of the flake to lock, the current one by default.
+    pub fn flake(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.flake = Some(dir.into());
+        self
+    }
+
+    /// Re-lock `input` to its latest revision.
+    pub fn update_input(mut self, input: impl Into<String>) -> Self {
+        self.update_inputs.push(input.into());
+        self
+    }
+
+    /// Lock `input` to `flake_ref` instead of what `flake.nix` says.
+    pub fn override_input(
+        mut self,
+        input: impl Into<String>,
+        flake_ref: impl Into<FlakeRef>,
+    ) -> Self {
+        self.override_inputs.push((input.into(), flake_ref.into()));
+        self
+    }
+
+    /// Update the lock file, returning what changed in it.
+    pub fn into_diff(mut self) -> Result<LockDiff, FlakeShowError> {
+        let dir = self.flake.clone().unwrap_or_else(|| PathBuf::from("."));
+        let runner = self.common.take_runner();
+
+        let before = read_lock(&dir)?;
+        runner.run(&self.build())?;
+        Ok(lock_diff(before, read_lock(&dir)?))
+    }
+
+    pub fn build(self) -> Command {
+        let mut cmd = self.common.command(&["flake", "lock"]);
+
+        if let Some(flake) = &self.flake {
+            cmd.arg(FlakeRef::path(flake).to_string());
+        }
+
+        for input in &self.update_inputs {
+            cmd.arg("--update-input").arg(input);
+        }
+
+        for (input, flake_ref) in &self.override_inputs {
+            cmd.arg("--override-input")
+                .arg(input)
+                .arg(flake_ref.to_string());
+        }
+
+        self.common.push_flags(&mut cmd);
+        cmd
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::scratch_dir;
+    use crate::{flake_lock, flake_update, NixBackend, NixInvocation, StderrLineHandler};
+
+    const BEFORE: &str = r#"{"nodes": {
+        "root": {"inputs": {"nixpkgs": "nixpkgs"}},
+        "nixpkgs": {
+            "locked": {"type": "github", "owner": "NixOS", "repo": "nixpkgs", "rev": "1111111aaaaaaa", "lastModified": 1709251200, "narHash": "sha256-a="},
+            "original": {"type": "github", "owner": "NixOS", "repo": "nixpkgs", "ref": "nixos-unstable"}
+        }
+    }, "root": "root", "version": 7}"#;
+
+    const AFTER: &str = r#"{"nodes": {
+        "root": {"inputs": {"nixpkgs": "nixpkgs"}},
+        "nixpkgs": {
+            "locked": {"type": "github", "owner": "NixOS", "repo": "nixpkgs", "rev": "4444444ddddddd", "lastModified": 1710028800, "narHash": "sha256-d="},
+            "original": {"type": "github", "owner": "NixOS", "repo": "nixpkgs", "ref": "nixos-unstable"}
+        }
+    }, "root": "root", "version": 7}"#;
+
+    /// Stands in for nix by writing the lock file it would have written.
+    #[derive(Debug)]
+    struct WritesLock {
+        dir: PathBuf,
+        expected_args: Vec<String>,
+    }
+
+    impl NixBackend for WritesLock {
+        fn run(
+            &self,
+            invocation: &NixInvocation,
+            _: Option<StderrLineHandler>,
+        ) -> Result<Vec<u8>, FlakeShowError> {
+            assert_eq!(invocation.args_lossy(), self.expected_args);
+            std::fs::write(self.dir.join("flake.lock"), AFTER).unwrap();
+            Ok(Vec::new())
+        }
+    }
+
+    fn flake_dir(name: &str, lock: Option<&str>) -> PathBuf {
+        let dir = scratch_dir(name);
+        if let Some(lock) = lock {
+            std::fs::write(dir.join("flake.lock"), lock).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn update_reports_changed_inputs() {
+        let dir = flake_dir("update", Some(BEFORE));
+        let flake = dir.display().to_string();
+
+        let diff = flake_update()
+            .input("nixpkgs")
+            .flake(&dir)
+            .backend(WritesLock {
+                dir: dir.clone(),
+                expected_args: ["flake", "update", "nixpkgs", "--flake", &flake]
+                    .map(String::from)
+                    .into(),
+            })
+            .into_diff()
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(diff.added.is_empty());
+        let nixpkgs = &diff.changed["nixpkgs"];
+        assert_eq!(
+            nixpkgs.rev.as_ref().unwrap().after.as_deref(),
+            Some("4444444ddddddd")
+        );
+        assert!(nixpkgs.nar_hash.is_some());
+    }
+
+    #[test]
+    fn first_lock_adds_everything() {
+        let dir = flake_dir("lock", None);
+        let flake = dir.display().to_string();
+
+        let diff = flake_lock()
+            .flake(&dir)
+            .override_input("nixpkgs", FlakeRef::github("NixOS", "nixpkgs"))
+            .backend(WritesLock {
+                dir: dir.clone(),
+                expected_args: [
+                    "flake",
+                    "lock",
+                    &flake,
+                    "--override-input",
+                    "nixpkgs",
+                    "github:NixOS/nixpkgs",
+                ]
+                .map(String::from)
+                .into(),
+            })
+            .into_diff()
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(diff.added.keys().collect::<Vec<_>>(), ["nixpkgs"]);
+        assert!(diff.changed.is_empty());
+    }
+
+    #[test]
+    fn relative_flakes_stay_git_paths() {
+        let args = |cmd: Command| {
+            cmd.get_args()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            args(flake_update().flake("sub").build()),
+            ["flake", "update", "--flake", "./sub"]
+        );
+        assert_eq!(
+            args(
+                flake_lock()
+                    .flake("sub")
+                    .override_input("x", FlakeRef::github("a", "b").with_ref("feature/x"))
+                    .build()
+            ),
+            [
+                "flake",
+                "lock",
+                "./sub",
+                "--override-input",
+                "x",
+                "github:a/b?ref=feature/x"
+            ]
+        );
+    }
+}
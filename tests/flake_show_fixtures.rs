@@ -0,0 +1,283 @@
+//! Parses recorded `nix flake show --json` output into `FlakeInfo`.
+//!
+//! Every file in `tests/fixtures/flake-show` is output of the command for
+//! one kind of flake, trimmed to keep it readable. Add new shapes there when
+//! nix changes what it prints.
+
+use std::path::{Path, PathBuf};
+
+use nix_flake_show::{
+    flake_show, Derivation, FlakeInfo, FlakeRef, NixOutput, OutputNode, ReplayBackend,
+};
+
+fn fixture_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/flake-show")
+}
+
+fn fixture(name: &str) -> FlakeInfo {
+    let path = fixture_dir().join(name);
+    let stdout = std::fs::read(&path).unwrap();
+    FlakeInfo::from_stdout(&stdout)
+        .unwrap_or_else(|err| panic!("{} doesn't parse: {err}", path.display()))
+}
+
+fn invocations(derivations: &[Derivation]) -> Vec<&str> {
+    derivations.iter().map(|d| d.invocation.as_str()).collect()
+}
+
+fn names<'a>(names: impl IntoIterator<Item = &'a String>) -> Vec<&'a str> {
+    names.into_iter().map(String::as_str).collect()
+}
+
+#[test]
+fn every_fixture_parses() {
+    let mut count = 0;
+    for entry in std::fs::read_dir(fixture_dir()).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            fixture(path.file_name().unwrap().to_str().unwrap());
+            count += 1;
+        }
+    }
+    assert!(count >= 7, "only found {count} fixtures");
+}
+
+#[test]
+fn templates_repo() {
+    let info = fixture("templates.json");
+
+    assert_eq!(info.templates.len(), 10);
+    assert_eq!(info.templates["rust"], "Rust template, using Naersk");
+    assert_eq!(info.templates["default"], info.templates["trivial"]);
+    assert!(info.packages.is_empty());
+    // the pre-`templates.default` name is kept as an output of its own
+    assert!(matches!(
+        info.other_output(&["defaultTemplate"]),
+        Some(OutputNode::Leaf { kind, .. }) if kind == "template"
+    ));
+}
+
+#[test]
+fn multi_system_packages() {
+    let info = fixture("multi-system.json");
+
+    assert_eq!(
+        names(info.packages.keys()),
+        [
+            "aarch64-darwin",
+            "aarch64-linux",
+            "x86_64-darwin",
+            "x86_64-linux"
+        ]
+    );
+
+    let linux = info.for_system("x86_64-linux");
+    assert_eq!(
+        invocations(&linux.packages),
+        ["default", "ripgrep", "ripgrep-static"]
+    );
+    let static_rg = linux
+        .packages
+        .iter()
+        .find(|d| d.invocation == "ripgrep-static")
+        .unwrap();
+    assert_eq!(
+        static_rg.name,
+        "ripgrep-static-x86_64-unknown-linux-musl-14.1.0"
+    );
+    assert_eq!(static_rg.kind, "derivation");
+    assert!(static_rg.description.is_some());
+    assert_eq!(invocations(&linux.checks), ["clippy", "fmt"]);
+    assert_eq!(invocations(&linux.dev_shells), ["default"]);
+    assert_eq!(linux.formatter.unwrap().invocation, "formatter");
+
+    let apps: Vec<_> = linux
+        .apps
+        .iter()
+        .map(|app| (app.invocation.as_str(), app.description.as_deref()))
+        .collect();
+    assert_eq!(
+        apps,
+        [("default", None), ("serve", Some("Serve the docs locally"))]
+    );
+
+    let intel_mac = info.for_system("x86_64-darwin");
+    assert_eq!(invocations(&intel_mac.packages), ["default", "ripgrep"]);
+    assert!(intel_mac.apps.is_empty());
+    assert!(intel_mac.formatter.is_none());
+}
+
+#[test]
+fn omitted_systems() {
+    let info = fixture("omitted-systems.json");
+
+    for sys in ["aarch64-darwin", "aarch64-linux", "x86_64-darwin"] {
+        let skipped = info.for_system(sys);
+        assert!(skipped.packages.is_empty(), "{sys}");
+        assert!(skipped.checks.is_empty(), "{sys}");
+        assert!(skipped.dev_shells.is_empty(), "{sys}");
+    }
+
+    let linux = info.for_system("x86_64-linux");
+    assert_eq!(invocations(&linux.packages), ["default", "hello"]);
+    assert_eq!(invocations(&linux.checks), ["tests"]);
+    assert_eq!(invocations(&linux.dev_shells), ["default"]);
+    assert_eq!(
+        info.omitted,
+        [
+            ["checks", "aarch64-darwin"],
+            ["checks", "aarch64-linux"],
+            ["checks", "x86_64-darwin"],
+            ["devShells", "aarch64-darwin"],
+            ["legacyPackages", "aarch64-darwin"],
+            ["legacyPackages", "x86_64-linux"],
+            ["packages", "aarch64-darwin"],
+            ["packages", "aarch64-linux"],
+            ["packages", "x86_64-darwin"],
+        ]
+    );
+    // without `--legacy` nix doesn't look inside legacyPackages at all
+    assert!(linux.legacy_packages.is_empty());
+    assert!(info.other_outputs.is_empty());
+}
+
+#[test]
+fn legacy_packages() {
+    let info = fixture("legacy-packages.json");
+
+    assert_eq!(info.lib, Some(OutputNode::Unknown));
+    assert_eq!(
+        invocations(&info.for_system("x86_64-linux").legacy_packages),
+        [
+            "hello",
+            "linuxKernel.kernels.linux_6_6",
+            "python3Packages.numpy",
+            "python3Packages.requests",
+        ]
+    );
+    assert_eq!(
+        invocations(&info.for_system("aarch64-darwin").legacy_packages),
+        ["hello", "python3Packages.requests"]
+    );
+    assert!(info.for_system("x86_64-linux").packages.is_empty());
+    // nix doesn't look into nixpkgs' `lib`, but it still reports it
+    assert_eq!(
+        info.other_output(&["legacyPackages", "x86_64-linux", "lib"]),
+        Some(&OutputNode::Unknown)
+    );
+    assert_eq!(info.other_outputs["legacyPackages"].leaves().len(), 1);
+}
+
+#[test]
+fn nixos_configurations() {
+    let info = fixture("nixos-configurations.json");
+
+    let configs: Vec<_> = info
+        .nixos_configurations
+        .iter()
+        .map(|c| (c.name.as_str(), c.kind.as_str()))
+        .collect();
+    assert_eq!(
+        configs,
+        [
+            ("desktop", "nixos-configuration"),
+            ("server", "nixos-configuration")
+        ]
+    );
+    assert_eq!(info.darwin_configurations[0].name, "work-mac");
+    assert_eq!(info.darwin_configurations[0].kind, "unknown");
+    assert_eq!(info.home_configurations[0].name, "andy@desktop");
+    assert_eq!(
+        names(info.nixos_modules.iter().map(|m| &m.name)),
+        ["default", "hardening"]
+    );
+    assert_eq!(info.overlays[0].name, "default");
+    assert_eq!(info.overlays[0].kind, "nixpkgs-overlay");
+
+    let jobs = info.for_system("x86_64-linux").hydra_jobs;
+    assert_eq!(jobs.len(), 1);
+    assert_eq!(jobs[0].path, ["desktop", "x86_64-linux"]);
+    assert_eq!(
+        jobs[0].derivation.name,
+        "nixos-system-desktop-24.05.20240316.c75037b"
+    );
+}
+
+#[test]
+fn unknown_outputs() {
+    let info = fixture("unknown-outputs.json");
+
+    assert_eq!(info.lib, Some(OutputNode::Unknown));
+    assert_eq!(
+        names(info.other_outputs.keys()),
+        ["colmena", "deploy", "herculesCI"]
+    );
+    assert_eq!(
+        info.other_output(&["deploy", "nodes", "server"]),
+        Some(&OutputNode::Unknown)
+    );
+    assert_eq!(
+        info.other_output(&["herculesCI"]),
+        Some(&OutputNode::Unknown)
+    );
+    assert_eq!(info.other_outputs["colmena"].leaves().len(), 2);
+    assert_eq!(
+        invocations(&info.for_system("x86_64-linux").packages),
+        ["default"]
+    );
+}
+
+#[test]
+fn legacy_default_package() {
+    let info = fixture("legacy-default-package.json");
+
+    // the pre-2022 singular outputs aren't folded into their modern
+    // counterparts, they show up as outputs of their own.
+    assert!(info.packages.is_empty());
+    assert!(info.dev_shells.is_empty());
+    assert!(info.overlays.is_empty());
+    assert_eq!(
+        names(info.other_outputs.keys()),
+        [
+            "defaultApp",
+            "defaultPackage",
+            "devShell",
+            "nixosModule",
+            "overlay"
+        ]
+    );
+    assert_eq!(
+        info.other_output(&["defaultPackage", "x86_64-linux"]),
+        Some(&OutputNode::Leaf {
+            kind: "derivation".to_string(),
+            name: Some("hello-2.12.1".to_string()),
+            description: Some("A program that produces a familiar, friendly greeting".to_string()),
+        })
+    );
+    assert!(matches!(
+        info.other_output(&["overlay"]),
+        Some(OutputNode::Leaf { kind, .. }) if kind == "nixpkgs-overlay"
+    ));
+}
+
+#[test]
+fn fixtures_replay_through_the_builder() {
+    let stdout = std::fs::read_to_string(fixture_dir().join("multi-system.json")).unwrap();
+    let backend = ReplayBackend::new().record(
+        [
+            "flake",
+            "show",
+            "github:BurntSushi/ripgrep",
+            "--all-systems",
+            "--json",
+        ],
+        NixOutput::success(stdout),
+    );
+
+    let info = flake_show()
+        .url(FlakeRef::github("BurntSushi", "ripgrep"))
+        .backend(backend)
+        .into_structured()
+        .unwrap();
+    assert_eq!(info.packages.len(), 4);
+}